@@ -98,7 +98,7 @@
           cargoLock.lockFile = ./Cargo.lock;
           useNextest = true;
 
-          nativeBuildInputs = [ ];
+          nativeBuildInputs = with pkgs; [ installShellFiles ];
           buildInputs = [ ] ++ darwinDeps;
 
           # makes no sense in a nix package
@@ -106,16 +106,12 @@
 
           preCheck = "export RUST_BACKTRACE=1";
 
-          # for clap apps
-          # postInstall = ''
-          #   $out/bin/twitch-archiver util mangen > ./twitch-archiver.1
-          #   installManPage ./twitch-archiver.1
-          #
-          #   installShellCompletion --cmd twitch-archiver \
-          #     --bash <($out/bin/twitch-archiver util completion --bash) \
-          #     --fish <($out/bin/twitch-archiver util completion --fish) \
-          #     --zsh  <($out/bin/twitch-archiver util completion --zsh)
-          # '';
+          postInstall = ''
+            installShellCompletion --cmd twitch-archiver \
+              --bash <($out/bin/twitch-archiver completions bash) \
+              --fish <($out/bin/twitch-archiver completions fish) \
+              --zsh  <($out/bin/twitch-archiver completions zsh)
+          '';
         };
         default = self.packages.${system}.twitch-archiver;
       };
@@ -0,0 +1,156 @@
+use crate::irc::Message;
+use anyhow::{Context, Result};
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How long to wait for the ROOMSTATE after joining before warning
+const JOIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The channels we should be in: the ones given as arguments plus the ones
+/// listed in the channels file, which is re-read whenever it changes
+pub struct Channels {
+    fixed: BTreeSet<String>,
+    file: Option<ChannelsFile>,
+}
+
+struct ChannelsFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    channels: BTreeSet<String>,
+}
+
+impl ChannelsFile {
+    fn read(&mut self) -> Result<BTreeSet<String>> {
+        let modified = self.path.metadata()?.modified().ok();
+        let contents = std::fs::read_to_string(&self.path)?;
+        self.modified = modified;
+        Ok(contents.lines().filter_map(normalize).collect())
+    }
+}
+
+fn normalize(channel: &str) -> Option<String> {
+    let channel = channel.trim();
+    let channel = channel.strip_prefix('#').unwrap_or(channel);
+    (!channel.is_empty()).then(|| channel.to_ascii_lowercase())
+}
+
+/// Twitch confirms joining a channel that exists with a ROOMSTATE, and
+/// sends nothing at all for a typo or a renamed channel, which would
+/// otherwise just look like a very quiet chat
+#[derive(Default)]
+pub struct JoinCheck {
+    pending: HashMap<String, Instant>,
+}
+
+impl JoinCheck {
+    pub fn joining(&mut self, channel: &str) {
+        let login = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_';
+        if channel.len() > 25 || !channel.chars().all(login) {
+            eprintln!("warning: #{channel} is not a valid Twitch login");
+        }
+        self.pending.insert(channel.to_owned(), Instant::now());
+    }
+
+    pub fn parting(&mut self, channel: &str) {
+        self.pending.remove(channel);
+    }
+
+    pub fn record(&mut self, msg: &Message) {
+        let Some(channel) = msg.channel() else {
+            return;
+        };
+        match msg.command {
+            "ROOMSTATE" => {}
+            "NOTICE"
+                if msg
+                    .tag("msg-id")
+                    .is_some_and(|id| id.0 == "msg_channel_suspended") =>
+            {
+                eprintln!("warning: #{channel} is suspended");
+            }
+            _ => return,
+        }
+        self.pending.remove(channel);
+    }
+
+    pub fn warn_if_due(&mut self) {
+        self.pending.retain(|channel, since| {
+            let due = since.elapsed() >= JOIN_TIMEOUT;
+            if due {
+                eprintln!(
+                    "warning: joined #{channel} {}s ago but got no ROOMSTATE, \
+                     the channel probably doesn't exist or was renamed",
+                    JOIN_TIMEOUT.as_secs()
+                );
+            }
+            !due
+        });
+    }
+}
+
+impl Channels {
+    pub fn new(fixed: &[String], path: Option<PathBuf>) -> Result<Self> {
+        let file = match path {
+            Some(path) => {
+                let mut file = ChannelsFile {
+                    path,
+                    modified: None,
+                    channels: BTreeSet::new(),
+                };
+                file.channels = file
+                    .read()
+                    .with_context(|| format!("failed to read {}", file.path.display()))?;
+                Some(file)
+            }
+            None => None,
+        };
+        Ok(Self {
+            fixed: fixed.iter().filter_map(|c| normalize(c)).collect(),
+            file,
+        })
+    }
+
+    pub fn all(&self) -> BTreeSet<&str> {
+        let from_file = self.file.iter().flat_map(|f| &f.channels);
+        self.fixed
+            .iter()
+            .chain(from_file)
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Re-read the channels file if it was modified since the last time,
+    /// returns the channels that have to be joined and parted
+    pub fn reload(&mut self) -> (Vec<String>, Vec<String>) {
+        let Some(file) = &mut self.file else {
+            return Default::default();
+        };
+        let modified = file.path.metadata().and_then(|m| m.modified()).ok();
+        if modified.is_none() || modified == file.modified {
+            return Default::default();
+        }
+        let channels = match file.read() {
+            Ok(channels) => channels,
+            Err(e) => {
+                eprintln!("failed to reload {}: {e}", file.path.display());
+                return Default::default();
+            }
+        };
+        let join = channels
+            .difference(&file.channels)
+            .filter(|c| !self.fixed.contains(*c))
+            .cloned()
+            .collect();
+        let part = file
+            .channels
+            .difference(&channels)
+            .filter(|c| !self.fixed.contains(*c))
+            .cloned()
+            .collect();
+        file.channels = channels;
+        (join, part)
+    }
+}
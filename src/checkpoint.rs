@@ -0,0 +1,100 @@
+use crate::{irc::Message, processor};
+use anyhow::{Context, Result};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// Don't rewrite the checkpoint file more often than this
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct Position {
+    ts: String,
+    id: String,
+}
+
+/// The last written message of every channel, kept in a file that looks like
+/// `channel tmi-sent-ts id` per line, so that after a crash it's known where
+/// the archive stops
+pub struct Checkpoint {
+    path: PathBuf,
+    channels: BTreeMap<String, Position>,
+    dirty: bool,
+    saved: Instant,
+}
+
+impl Checkpoint {
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let mut channels = BTreeMap::new();
+        if path.exists() {
+            let contents = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            for line in contents.lines() {
+                let mut parts = line.split(' ');
+                if let (Some(channel), Some(ts), Some(id)) =
+                    (parts.next(), parts.next(), parts.next())
+                {
+                    let position = Position {
+                        ts: ts.to_owned(),
+                        id: id.to_owned(),
+                    };
+                    channels.insert(channel.to_owned(), position);
+                }
+            }
+        }
+        Ok(Self {
+            path,
+            channels,
+            dirty: false,
+            saved: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, msg: &Message) {
+        let (Some(channel), Some(ts)) = (msg.channel(), msg.tag("tmi-sent-ts")) else {
+            return;
+        };
+        let position = match self.channels.get_mut(channel) {
+            Some(position) => position,
+            None => self.channels.entry(channel.to_owned()).or_default(),
+        };
+        // reusing the strings, this is called for every message
+        position.ts.clear();
+        position.ts.push_str(&ts.0);
+        position.id.clear();
+        position.id.push_str(msg.tag("id").map_or("-", |id| &id.0));
+        self.dirty = true;
+    }
+
+    pub fn save_if_due(&mut self) -> Result<()> {
+        if self.dirty && self.saved.elapsed() >= SAVE_INTERVAL {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&mut self) -> Result<()> {
+        let mut contents = String::new();
+        for (channel, Position { ts, id }) in &self.channels {
+            // compress() base64s the ids, put them back into the usual form
+            let id = match processor::decode_id(id) {
+                Some(uuid) => uuid.to_string(),
+                None => id.clone(),
+            };
+            writeln!(contents, "{channel} {ts} {id}")?;
+        }
+
+        // write-and-rename so that a crash never leaves a half-written file
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, contents)?;
+        std::fs::rename(&tmp, &self.path)?;
+
+        self.dirty = false;
+        self.saved = Instant::now();
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{
+    irc::{Message, TagValue},
+    repair::{self, Edit},
+};
+use anyhow::Result;
+use smallvec::{smallvec, SmallVec};
+use std::{borrow::Cow, path::Path};
+
+/// The logins and ids of the users that the message is from or about: the
+/// author, the target of a ban and the recipient of a gift sub
+pub fn users<'a>(msg: &'a Message) -> SmallVec<[&'a str; 8]> {
+    const TAGS: &[&str] = &[
+        "user-id",
+        "login",
+        "target-user-id",
+        "msg-param-recipient-id",
+        "msg-param-recipient-user-name",
+    ];
+    let tags = TAGS.iter().filter_map(|key| msg.tag(key)).map(|v| &*v.0);
+    let sender = msg.prefix.as_ref().map(|p| p.nick);
+    // the user of CLEARCHAT is the second param
+    let banned = msg
+        .params
+        .get(1)
+        .filter(|_| msg.command == "CLEARCHAT")
+        .copied();
+    tags.chain(sender).chain(banned).collect()
+}
+
+/// Take the user's joins and parts out of a PRESENCE batch, returns how
+/// many were taken out and the edit to make
+fn erase_presence(msg: &Message, user: &str) -> (usize, Edit) {
+    let (Some(&channel), Some(events)) = (msg.params.first(), msg.params.get(1)) else {
+        return (0, Edit::Keep);
+    };
+    let all = events.split(' ').count();
+    let kept: Vec<_> = events
+        .split(' ')
+        .filter(|e| {
+            !e.get(1..)
+                .is_some_and(|login| login.eq_ignore_ascii_case(user))
+        })
+        .collect();
+    let erased = all - kept.len();
+    if erased == 0 {
+        return (0, Edit::Keep);
+    }
+    if kept.is_empty() {
+        return (erased, Edit::Drop);
+    }
+    let kept = kept.join(" ");
+    let edited = Message {
+        tags: msg
+            .tags
+            .iter()
+            .map(|(k, v)| (*k, TagValue(Cow::Borrowed(&*v.0))))
+            .collect(),
+        prefix: None,
+        command: msg.command,
+        params: smallvec![channel, &*kept],
+    };
+    let mut line = Vec::new();
+    edited.write(&mut line).expect("writing to a vec");
+    (
+        erased,
+        Edit::Replace(String::from_utf8_lossy(&line).into_owned()),
+    )
+}
+
+/// Remove all the messages of a user (a login or a numeric id) from an
+/// archive file in place, along with their joins and parts in the PRESENCE
+/// batches, returns how many were removed
+pub fn erase(path: &Path, user: &str) -> Result<usize> {
+    let mut erased = 0;
+    repair::rewrite(path, None, |msg| {
+        if msg.command == "PRESENCE" {
+            let (count, edit) = erase_presence(msg, user);
+            erased += count;
+            return edit;
+        }
+        if users(msg).iter().any(|u| u.eq_ignore_ascii_case(user)) {
+            erased += 1;
+            return Edit::Drop;
+        }
+        Edit::Keep
+    })?;
+    Ok(erased)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn presence(events: &str, user: &str) -> (usize, Option<String>) {
+        let line = format!("@time=x PRESENCE #chan :{events}");
+        let (erased, edit) = erase_presence(&Message::parse(&line), user);
+        let edited = match edit {
+            Edit::Keep => Some(line),
+            Edit::Drop => None,
+            Edit::Replace(line) => Some(line),
+        };
+        (erased, edited)
+    }
+
+    #[test]
+    fn presence_batches() {
+        assert_eq!(
+            presence("+alice +bob -carol", "bob"),
+            (1, Some("@time=x PRESENCE #chan :+alice -carol".into()))
+        );
+        // a join and a part, in whatever case the login was given
+        assert_eq!(
+            presence("+bob +alice -bob", "Bob"),
+            (2, Some("@time=x PRESENCE #chan :+alice".into()))
+        );
+        assert_eq!(presence("+bob -bob", "bob"), (2, None));
+        // only the whole login matches
+        assert_eq!(
+            presence("+bobby +abob", "bob"),
+            (0, Some("@time=x PRESENCE #chan :+bobby +abob".into()))
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{
+    irc::{self, Message, TagValue},
+    open_log,
+    timezone::Timezone,
+};
+use anyhow::{Context, Result};
+use chrono::{NaiveDate, NaiveTime};
+use smallvec::smallvec;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::{BufRead, Write},
+    path::Path,
+};
+use uuid::Uuid;
+
+/// The namespace of the made up message ids, so that importing the same
+/// logs twice gives the same ids
+const ID_NAMESPACE: Uuid = Uuid::from_u128(0x5b1b_3c3e_8e0a_4f57_a3c4_9c1e_7f2d_6a10);
+
+/// The channel and the date from a `channel-2024-01-31.log` file name
+fn parse_name(name: &str) -> Option<(&str, NaiveDate)> {
+    let name = name.strip_suffix(".log")?;
+    let i = name.len().checked_sub(11)?;
+    let channel = name.get(..i).filter(|c| !c.is_empty())?;
+    let date = name.get(i..)?.strip_prefix('-')?.parse().ok()?;
+    Some((channel, date))
+}
+
+#[derive(Debug, PartialEq)]
+struct User<'a> {
+    display: &'a str,
+    login: String,
+}
+
+/// The time, the user if it's a chat message, and the text of a
+/// `[HH:MM:SS] user: message` line
+fn parse_line(line: &str) -> Option<(NaiveTime, Option<User<'_>>, &str)> {
+    // '# Start logging at ...' and such
+    let (time, text) = line.strip_prefix('[')?.split_once("] ")?;
+    let time = NaiveTime::parse_from_str(time, "%H:%M:%S").ok()?;
+
+    let chat = text.split_once(": ").and_then(|(name, text)| {
+        // localized names are logged as `display (login)`
+        let (display, login) = match name.split_once(" (") {
+            Some((display, login)) => (display, login.strip_suffix(')')?),
+            None => (name, name),
+        };
+        let login = login.to_ascii_lowercase();
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let valid = !login.is_empty() && login.chars().all(valid);
+        valid.then_some((User { display, login }, text))
+    });
+    // anything that doesn't look like a chat message is a system message,
+    // like the subs and timeouts
+    Some(match chat {
+        Some((user, text)) => (time, Some(user), text),
+        None => (time, None, text),
+    })
+}
+
+/// Convert a Chatterino log file, named like `channel-2024-01-31.log`, with
+/// `[HH:MM:SS] user: message` lines, into IRC lines
+pub fn chatterino(path: &Path, tz: Timezone, mut out: impl Write) -> Result<()> {
+    let (channel, date) = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .and_then(parse_name)
+        .with_context(|| {
+            format!(
+                "{} is not named like channel-YYYY-MM-DD.log",
+                path.display()
+            )
+        })?;
+    let channel = format!("#{}", channel.to_ascii_lowercase());
+
+    // the same message in the same second (spam) still needs its own id
+    let mut seen = HashMap::<String, u32>::new();
+
+    let contents = open_log(path)?;
+    for (i, line) in contents.lines().enumerate() {
+        let line = line?;
+        let Some((time, user, text)) = parse_line(&line) else {
+            continue;
+        };
+        // the hour skipped when the clocks go forward
+        let Some(time) = tz.to_utc(date.and_time(time)) else {
+            eprintln!(
+                "{}:{}: skipped, {date} {time} doesn't exist in this timezone",
+                path.display(),
+                i + 1
+            );
+            continue;
+        };
+        let ts = time.timestamp_millis().to_string();
+
+        let mut id_source = format!("{channel}\0{ts}\0");
+        if let Some(user) = &user {
+            id_source.push_str(&user.login);
+            id_source.push('\0');
+        }
+        id_source.push_str(text);
+        let repeats = seen.entry(id_source.clone()).or_default();
+        // the first one keeps the id it had before repeats were counted
+        if *repeats > 0 {
+            id_source.push_str(&format!("\0{repeats}"));
+        }
+        *repeats += 1;
+        let id = Uuid::new_v5(&ID_NAMESPACE, id_source.as_bytes()).to_string();
+
+        let msg = match &user {
+            Some(User { display, login }) => Message {
+                tags: vec![
+                    ("display-name", TagValue(Cow::Borrowed(display))),
+                    ("id", TagValue(Cow::Owned(id))),
+                    ("tmi-sent-ts", TagValue(Cow::Borrowed(&ts))),
+                ],
+                prefix: Some(irc::Prefix {
+                    nick: login,
+                    user: None,
+                    host: None,
+                }),
+                command: "PRIVMSG",
+                params: smallvec![&*channel, text],
+            },
+            None => Message {
+                tags: vec![
+                    ("id", TagValue(Cow::Owned(id))),
+                    ("tmi-sent-ts", TagValue(Cow::Borrowed(&ts))),
+                ],
+                prefix: None,
+                command: "NOTICE",
+                params: smallvec![&*channel, text],
+            },
+        };
+        msg.write(&mut out)?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(h: u32, m: u32, s: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, s).unwrap()
+    }
+
+    #[test]
+    fn file_names() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+        assert_eq!(parse_name("forsen-2024-01-31.log"), Some(("forsen", date)));
+        // channels can have dashes, the date is always the last 10 chars
+        assert_eq!(parse_name("a-b-2024-01-31.log"), Some(("a-b", date)));
+        assert_eq!(parse_name("-2024-01-31.log"), None);
+        assert_eq!(parse_name("forsen-2024-13-01.log"), None);
+        assert_eq!(parse_name("forsen_2024-01-31.log"), None);
+        assert_eq!(parse_name("forsen-2024-01-31.txt"), None);
+        assert_eq!(parse_name("x.log"), None);
+    }
+
+    #[test]
+    fn chat_lines() {
+        let user = |display, login: &str| {
+            Some(User {
+                display,
+                login: login.to_owned(),
+            })
+        };
+        assert_eq!(
+            parse_line("[12:00:01] Alice: LUL"),
+            Some((time(12, 0, 1), user("Alice", "alice"), "LUL"))
+        );
+        assert_eq!(
+            parse_line("[12:00:02] 爱丽丝 (alice): hi: there"),
+            Some((time(12, 0, 2), user("爱丽丝", "alice"), "hi: there"))
+        );
+        assert_eq!(
+            parse_line("[12:00:03] Bob: #tag hi there"),
+            Some((time(12, 0, 3), user("Bob", "bob"), "#tag hi there"))
+        );
+    }
+
+    #[test]
+    fn system_lines() {
+        assert_eq!(
+            parse_line("[12:00:04] alice has been timed out for 10s."),
+            Some((time(12, 0, 4), None, "alice has been timed out for 10s."))
+        );
+        // not a login, so not a chat message
+        assert_eq!(
+            parse_line("[12:00:05] Now hosting: someone"),
+            Some((time(12, 0, 5), None, "Now hosting: someone"))
+        );
+        assert_eq!(parse_line("# Start logging at 2024-01-31"), None);
+        assert_eq!(parse_line("[25:00:00] alice: hi"), None);
+        assert_eq!(parse_line("[12:00:06]"), None);
+    }
+
+    fn import(channel: &str, contents: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("import-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{channel}-2024-01-31.log"));
+        std::fs::write(&path, contents).unwrap();
+        let mut out = Vec::new();
+        chatterino(&path, Timezone::Named(chrono_tz::UTC), &mut out).unwrap();
+        std::fs::remove_file(path).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn repeated_messages_get_their_own_ids() {
+        let out = import("spam", "[12:00:01] alice: LUL\n[12:00:01] alice: LUL\n");
+        let ids: Vec<_> = out
+            .lines()
+            .map(|line| Message::parse(line).tag("id").unwrap().0.to_string())
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0], ids[1]);
+        // and the same ones on every import
+        let again = import("spam", "[12:00:01] alice: LUL\n[12:00:01] alice: LUL\n");
+        assert_eq!(out, again);
+    }
+
+    #[test]
+    fn text_starting_with_a_hash_survives() {
+        let out = import("chan", "[12:00:02] Bob: #tag hi there\n");
+        let msg = Message::parse(out.trim_end());
+        assert_eq!(msg.params.as_slice(), ["#chan", "#tag hi there"]);
+    }
+}
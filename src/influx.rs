@@ -0,0 +1,65 @@
+use crate::irc::Message;
+use std::io::{self, Write};
+
+/// The USERNOTICE params with numbers worth graphing, and their field names
+const NUMERIC_PARAMS: &[(&str, &str)] = &[
+    ("msg-param-cumulative-months", "months"),
+    ("msg-param-mass-gift-count", "gifts"),
+    ("msg-param-viewerCount", "viewers"),
+];
+
+/// Tag values can't have unescaped commas, spaces or equal signs
+fn write_tag<W: Write>(w: &mut W, key: &str, value: &str) -> io::Result<()> {
+    write!(w, ",{key}=")?;
+    for c in value.chars() {
+        if matches!(c, ',' | ' ' | '=') {
+            write!(w, "\\")?;
+        }
+        write!(w, "{c}")?;
+    }
+    Ok(())
+}
+
+/// A point like `twitch,channel=x,command=PRIVMSG count=1i,bits=100i <ns>`,
+/// without the text, for chat activity dashboards
+pub fn write<W: Write>(msg: &Message, user: bool, mut w: W) -> io::Result<()> {
+    write!(w, "twitch")?;
+    if let Some(channel) = msg.channel() {
+        write_tag(&mut w, "channel", channel)?;
+    }
+    write_tag(&mut w, "command", msg.command)?;
+    if user {
+        // USERNOTICEs come from the server, with the user in the login tag
+        let login = match msg.tag("login") {
+            Some(login) => Some(&*login.0),
+            None => msg
+                .prefix
+                .as_ref()
+                .map(|p| p.nick)
+                .filter(|n| !n.contains('.')),
+        };
+        if let Some(login) = login {
+            write_tag(&mut w, "user", login)?;
+        }
+    }
+    if let Some(event) = msg.tag("msg-id") {
+        write_tag(&mut w, "event", &event.0)?;
+    }
+
+    write!(w, " count=1i")?;
+    let numbers = [("bits", "bits")].iter().chain(NUMERIC_PARAMS);
+    for (tag, field) in numbers {
+        if let Some(n) = msg.tag(tag).and_then(|v| v.0.parse::<i64>().ok()) {
+            write!(w, ",{field}={n}i")?;
+        }
+    }
+
+    // points of a series with the same timestamp overwrite each other, so
+    // the nanoseconds under the (millisecond) tmi-sent-ts come from the id
+    let nanos = msg.tag("id").map_or(0, |id| {
+        id.0.bytes()
+            .fold(0u32, |hash, b| hash.wrapping_mul(31).wrapping_add(b as u32))
+            % 1_000_000
+    });
+    write!(w, " {}{nanos:06}", msg.sent_time().timestamp_millis())
+}
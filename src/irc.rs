@@ -1,14 +1,20 @@
+use chrono::{DateTime, Utc};
 use smallvec::SmallVec;
 use std::borrow::Cow;
 use std::fmt::{self, Display};
 use std::io::Write;
 
+/// Format the time in the IRCv3 `server-time` format
+pub fn server_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
 #[repr(transparent)]
 #[derive(Debug)]
 pub struct TagValue<'m>(pub Cow<'m, str>);
 
 impl<'m> TagValue<'m> {
-    pub fn unescape(&self) -> Cow<str> {
+    pub fn unescape(&self) -> Cow<'_, str> {
         if let Cow::Owned(ref s) = self.0 {
             return Cow::Borrowed(s);
         }
@@ -89,6 +95,24 @@ pub struct Message<'m> {
 }
 
 impl<'m> Message<'m> {
+    pub fn tag(&self, key: &str) -> Option<&TagValue<'m>> {
+        self.tags.iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    /// The channel name (without the #) if the first param is a channel
+    pub fn channel(&self) -> Option<&'m str> {
+        self.params.first()?.strip_prefix('#')
+    }
+
+    /// When the message was sent according to tmi-sent-ts, or now if the
+    /// message doesn't have one
+    pub fn sent_time(&self) -> DateTime<Utc> {
+        self.tag("tmi-sent-ts")
+            .and_then(|ts| ts.0.parse().ok())
+            .and_then(DateTime::from_timestamp_millis)
+            .unwrap_or_else(Utc::now)
+    }
+
     pub fn write<W: Write>(&self, mut w: W) -> std::io::Result<()> {
         if let Some(((last_k, last_v), rest)) = self.tags.split_last() {
             write!(w, "@")?;
@@ -115,7 +139,8 @@ impl<'m> Message<'m> {
 
             // fixme: this is a quick hack,
             // do we really have to remember if last arg should have : prefix?
-            if last.starts_with('#') {
+            // (a trailing text with spaces that starts with # still needs it)
+            if last.starts_with('#') && !last.contains(' ') {
                 write!(w, " {last}")?;
             } else {
                 write!(w, " :{last}")?;
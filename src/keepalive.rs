@@ -0,0 +1,73 @@
+use crate::irc::Message;
+use anyhow::{bail, Result};
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+/// How long the server can be silent before we ping it ourselves
+const IDLE: Duration = Duration::from_secs(60);
+/// How long a ping can go unanswered before the connection is considered stale
+const STALE: Duration = Duration::from_secs(15);
+/// And before giving up on it and reconnecting
+const DEAD: Duration = Duration::from_secs(60);
+
+const TOKEN: &str = "twitch-archiver";
+
+/// Pings the server when it's been quiet for a while, to tell a half-dead
+/// connection apart from a quiet chat, and measures the round trip
+pub struct Keepalive {
+    last_read: Instant,
+    /// When our unanswered ping was sent
+    ping: Option<Instant>,
+    warned: bool,
+}
+
+impl Keepalive {
+    pub fn new() -> Self {
+        Self {
+            last_read: Instant::now(),
+            ping: None,
+            warned: false,
+        }
+    }
+
+    pub fn read(&mut self) {
+        self.last_read = Instant::now();
+    }
+
+    /// Returns the round trip time if the message is the reply to our ping
+    pub fn pong(&mut self, msg: &Message) -> Option<Duration> {
+        if msg.command != "PONG" || msg.params.last() != Some(&TOKEN) {
+            return None;
+        }
+        let rtt = self.ping.take()?.elapsed();
+        if self.warned {
+            eprintln!("the connection is alive again, ping took {rtt:?}");
+            self.warned = false;
+        }
+        Some(rtt)
+    }
+
+    pub fn check(&mut self, stream: &mut impl Write) -> Result<()> {
+        let Some(sent) = self.ping else {
+            if self.last_read.elapsed() >= IDLE {
+                write!(stream, "PING :{TOKEN}\r\n")?;
+                self.ping = Some(Instant::now());
+            }
+            return Ok(());
+        };
+        let waited = sent.elapsed();
+        if waited >= DEAD {
+            bail!("no reply to a ping in {} seconds", waited.as_secs());
+        }
+        if waited >= STALE && !self.warned {
+            eprintln!(
+                "the connection looks stale, nothing received for {} seconds and no reply to a ping",
+                self.last_read.elapsed().as_secs()
+            );
+            self.warned = true;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use chrono::{DateTime, Utc};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How long the holder can go without renewing before the lease is up for
+/// grabs
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Don't touch the (maybe shared) file more often than this
+const RENEW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A leader lease through a file shared by redundant instances. The holder
+/// writes its id into it every tick, the others stay connected but don't
+/// write anything until the holder stops renewing it
+pub struct Lease {
+    path: PathBuf,
+    id: String,
+    held: bool,
+    renewed: Option<Instant>,
+}
+
+impl Lease {
+    pub fn new(path: PathBuf) -> Self {
+        let started = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        Self {
+            path,
+            id: format!("{}-{started}", std::process::id()),
+            held: false,
+            renewed: None,
+        }
+    }
+
+    pub fn held(&self) -> bool {
+        self.held
+    }
+
+    /// Renew or try to take over the lease, returns when the previous holder
+    /// last renewed it if it was just taken over
+    pub fn renew_if_due(&mut self) -> Option<DateTime<Utc>> {
+        if self.renewed.is_some_and(|r| r.elapsed() < RENEW_INTERVAL) {
+            return None;
+        }
+        self.renewed = Some(Instant::now());
+        let holder = std::fs::read_to_string(&self.path).ok();
+        let renewed = self.path.metadata().and_then(|m| m.modified()).ok();
+        let age = renewed.and_then(|r| r.elapsed().ok());
+        let expired = !matches!(age, Some(age) if age < TIMEOUT);
+
+        match holder {
+            Some(holder) if holder.trim() == self.id => {}
+            Some(holder) if !expired => {
+                if self.held {
+                    eprintln!("lost the lease in {} to {holder}", self.path.display());
+                    self.held = false;
+                }
+                return None;
+            }
+            _ => {}
+        }
+        if let Err(e) = std::fs::write(&self.path, &self.id) {
+            eprintln!("failed to renew the lease in {}: {e}", self.path.display());
+            // a holder keeps writing, better twice than not at all
+            return None;
+        }
+        if self.held {
+            return None;
+        }
+        eprintln!("took over the lease in {}", self.path.display());
+        self.held = true;
+        renewed.filter(|_| expired).map(DateTime::from)
+    }
+}
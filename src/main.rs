@@ -1,23 +1,168 @@
-use anyhow::{bail, Result};
-use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
-use clap::Parser;
-use file_rotate::{compression::Compression, suffix::AppendCount, ContentLimit, FileRotate};
-use irc::Message;
+use anyhow::{bail, Context, Result};
+use channels::{Channels, JoinCheck};
+use checkpoint::Checkpoint;
+use chrono::{DateTime, Utc};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use flate2::read::GzDecoder;
+use irc::{Message, TagValue};
+use keepalive::Keepalive;
+use lease::Lease;
+use output::{Output, OutputFormat, WriteErrorPolicy};
+use processor::{Action, Pipeline};
+use sample::SampleRate;
+use smallvec::smallvec;
+use stats::Stats;
+use statsd::Statsd;
 use std::{
     borrow::Cow,
-    io::{BufRead, BufReader, Write},
-    path::PathBuf,
-    time::Duration,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 use tcp_stream::{TLSConfig, TcpStream};
+use template::ChannelIds;
+use timezone::Timezone;
 
+mod channels;
+mod checkpoint;
+mod erase;
+mod import;
+mod influx;
 mod irc;
+mod keepalive;
+mod lease;
+mod optout;
+mod output;
+mod presence;
+mod pretty;
+mod processor;
+mod repair;
+mod sample;
+mod seen;
+mod stats;
+mod statsd;
+mod syslog;
+mod template;
+mod timezone;
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the completion script for the given shell
+    Completions { shell: Shell },
+    /// Turn the base64 message ids that compression writes back into the
+    /// usual uuids in an archive file (which can be gzipped)
+    Repair {
+        file: PathBuf,
+        /// Write the repaired archive here instead of replacing the file
+        #[arg(short)]
+        output: Option<PathBuf>,
+    },
+    /// Remove every message from or about a user (their chat messages, subs,
+    /// bans, gifted subs, joins and parts) from archive files, rewriting them
+    /// in place. The current log of a running archiver can be erased too, but
+    /// the messages it writes while the file is being rewritten can be lost,
+    /// so better stop it or erase only the rotated .gz files
+    Erase {
+        /// The login or the numeric user id
+        #[arg(long)]
+        user: String,
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Convert logs from other tools into IRC lines on stdout, to be piped
+    /// into --stdin
+    Import {
+        #[command(subcommand)]
+        source: ImportSource,
+    },
+}
+
+#[derive(Subcommand)]
+enum ImportSource {
+    /// Chatterino's daily log files, named like channel-2024-01-31.log.
+    /// Message ids are made from the contents, so re-importing gives the
+    /// same ones
+    Chatterino {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// The timezone of the times in the logs, the one of the computer
+        /// Chatterino was running on
+        #[arg(long, default_value = "local")]
+        timezone: Timezone,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum TwitchCap {
+    /// twitch.tv/tags, the badges, colors, ids, timestamps etc
+    Tags,
+    /// twitch.tv/commands, USERNOTICE, CLEARCHAT, ROOMSTATE etc
+    Commands,
+    /// twitch.tv/membership, the JOIN/PART events
+    Membership,
+}
+
+impl TwitchCap {
+    fn name(self) -> &'static str {
+        match self {
+            TwitchCap::Tags => "twitch.tv/tags",
+            TwitchCap::Commands => "twitch.tv/commands",
+            TwitchCap::Membership => "twitch.tv/membership",
+        }
+    }
+}
 
 #[derive(Parser)]
+#[command(disable_help_subcommand = true, args_conflicts_with_subcommands = true)]
 struct Args {
-    /// The channels to read from
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// The channels to read from. A channel named like one of the commands
+    /// has to come after a --, e.g. `twitch-archiver -- import`
     #[arg()]
     channels: Vec<String>,
+    /// A file with more channels to read from, one per line.
+    /// The file is watched, and channels are joined or parted as it changes
+    #[arg(long)]
+    channels_file: Option<PathBuf>,
+    /// Instead of connecting to Twitch, replay an archived log file (which
+    /// can be gzipped) through the output at the original pace
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+    /// How many times faster than the original the replay goes
+    #[arg(long, default_value_t = 1.0, requires = "replay")]
+    speed: f64,
+    /// Instead of connecting to Twitch, archive raw IRC lines read from stdin,
+    /// e.g. when piped from another relay
+    #[arg(long, conflicts_with = "replay")]
+    stdin: bool,
+    /// Stop cleanly after writing this many messages
+    #[arg(long, value_name = "N")]
+    max_messages: Option<u64>,
+    /// Stop cleanly after running for this many seconds. With --stdin it's
+    /// only checked when a line comes in
+    #[arg(long, value_name = "SECONDS")]
+    max_duration: Option<u64>,
+    /// The IRC server to connect to, non-Twitch servers work too, minus the
+    /// Twitch-specific tags
+    #[arg(long, default_value = "irc.chat.twitch.tv")]
+    server: String,
+    /// The port of the IRC server, 6697 by default or 6667 with --no-tls
+    #[arg(long)]
+    port: Option<u16>,
+    /// Connect to the IRC server without TLS
+    #[arg(long)]
+    no_tls: bool,
+    /// The Twitch capabilities to request, comma-separated. By default those
+    /// are tags and commands, plus membership with --dont-filter.
+    /// --track-presence always adds membership
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "CAPS")]
+    twitch_caps: Option<Vec<TwitchCap>>,
+    /// Request an additional IRCv3 capability, can be given multiple times
+    #[arg(long)]
+    cap: Vec<String>,
     /// What nick to use for auth, defaults to an anonymous Twitch user
     #[arg(short, long)]
     nick: Option<String>,
@@ -27,139 +172,609 @@ struct Args {
     pass: Option<String>,
     /// The file to write logs to, will be rotated and compressed.
     /// By default logs are just printed to stdout.
-    /// If no name is given the file will be called twitch.log
+    /// If no name is given the file will be called twitch.log.
+    /// The path can contain {channel}, {channel_id}, {command}, {date}, {year}
+    /// and {month}, which are filled in for every message (the dates in the
+    /// --timezone), e.g. logs/{channel}/{date}.log. The channel id (room-id) stays the same
+    /// when the channel is renamed, with it the room-id tag is not compressed
+    /// away, so that the archive can be replayed into the same paths
     #[arg(short)]
     output: Option<Option<PathBuf>>,
+    /// Send the messages to a syslog server instead, as RFC 5424 records.
+    /// The address looks like udp://host:514, tcp://host:601 or tls://host:6514
+    #[arg(long, value_name = "URL", conflicts_with = "output")]
+    syslog: Option<String>,
+    /// Send InfluxDB line protocol points (see --format influx) over TCP to
+    /// this address instead, e.g. QuestDB's ILP port localhost:9009.
+    /// The user is added as a tag too, QuestDB symbols handle that many
+    #[arg(
+        long,
+        value_name = "HOST:PORT",
+        conflicts_with_all = ["output", "syslog", "format"]
+    )]
+    ilp: Option<String>,
+    /// Do everything except writing the messages anywhere, and print the
+    /// per-channel stats every minute (or --stats-interval) instead, to try
+    /// out the channels and filters
+    #[arg(
+        long,
+        conflicts_with_all = ["output", "syslog", "ilp", "checkpoint", "seen_users", "lease"]
+    )]
+    dry_run: bool,
+    /// Also append every line exactly as it was received to this file,
+    /// before any decoding, filtering or compression, for debugging
+    #[arg(long, value_name = "FILE")]
+    raw_tap: Option<PathBuf>,
+    /// Every this many seconds, fsync the output files (and the dead letter
+    /// file) so that a power loss can't lose more than that. The lines are
+    /// never buffered by the archiver itself, 0 syncs after every message.
+    /// They are also synced once more when the archiver stops, and a failed
+    /// sync is only logged
+    #[arg(long, value_name = "SECONDS")]
+    fsync: Option<u64>,
     /// The size (in bytes) that has to be surpassed for the file to be rotated
     /// Default value is 128 MiB (2^27 bytes)
     #[arg(long)]
     rotation_limit: Option<usize>,
+    /// The format in which the messages are written
+    #[arg(long, value_enum, default_value_t = OutputFormat::Irc)]
+    format: OutputFormat,
+    /// Add a `time` tag with the receive time (ISO 8601, UTC) to the messages
+    /// that have no `tmi-sent-ts` tag, like server notices
+    #[arg(long)]
+    receive_time: bool,
+    /// The timezone for the times in the pretty format and for the {date},
+    /// {year} and {month} in the output path, either 'local' or an IANA name
+    /// like 'Europe/Kyiv'. With 'local' the files are split at the host's
+    /// midnight and follow its DST changes, use 'UTC' for fixed day boundaries
+    #[arg(long, default_value = "local")]
+    timezone: Timezone,
+    /// Color the names in the pretty format with the users' chat colors
+    #[arg(long)]
+    color: bool,
     /// Dont filter out any messages (except PING).
     /// By default, Twitch server welcome messages and JOIN/PART are filtered
     /// away
     #[arg(long)]
     dont_filter: bool,
+    /// Drop this tag from all the messages, can be given multiple times
+    #[arg(long, value_name = "TAG")]
+    drop_tag: Vec<String>,
+    /// Never drop or rewrite this tag when compressing messages, can be given
+    /// multiple times
+    #[arg(long, value_name = "TAG")]
+    keep_tag: Vec<String>,
+    /// Don't compress the messages at all, keep the tags and prefixes exactly
+    /// as they came (except for the tags given with --drop-tag)
+    #[arg(long)]
+    no_compress: bool,
+    /// Never write the messages from or about the users listed in this file,
+    /// one login or user id per line. The file is re-read when it changes
+    #[arg(long, value_name = "FILE")]
+    opt_out: Option<PathBuf>,
+    /// Only keep this percentage of the chat messages, as [CHANNEL=]PERCENT.
+    /// Without a channel it applies to every channel that has no rate of its
+    /// own. Other events like subs and bans are always kept, and which
+    /// messages are kept is decided by their id, so it's the same every run
+    #[arg(long, value_name = "[CHANNEL=]PERCENT")]
+    sample: Vec<SampleRate>,
+    /// Keep the timestamp and id of the last written message of every channel
+    /// in this file, to know where the archive ends after a crash
+    #[arg(long, value_name = "FILE")]
+    checkpoint: Option<PathBuf>,
+    /// Run as one of several redundant instances sharing this file (on the
+    /// same machine or a shared filesystem). Only the one holding the lease
+    /// opens the output and writes, the others stay connected and take over
+    /// when it stops renewing the lease for 10 seconds
+    #[arg(long, value_name = "FILE")]
+    lease: Option<PathBuf>,
+    /// Add a first-archived=1 tag to the first message of every user in a
+    /// channel, the user ids seen so far are kept in a file per channel in
+    /// this directory. Unlike Twitch's first-msg, this only depends on what
+    /// was archived
+    #[arg(long, value_name = "DIR")]
+    seen_users: Option<PathBuf>,
+    /// After reconnecting, write a GAP record to every channel with the time
+    /// span (in gap-start/gap-end tags) during which nothing was received
+    #[arg(long)]
+    mark_gaps: bool,
+    /// Every this many minutes, print per-channel message rates and written
+    /// byte counts to stderr
+    #[arg(long, value_name = "MINUTES")]
+    stats_interval: Option<u64>,
+    /// Send the per-channel message and byte counters, and the ping to the
+    /// server, to this statsd address over UDP every 10 seconds
+    #[arg(long, value_name = "HOST:PORT")]
+    statsd: Option<String>,
+    /// Request JOIN/PART events and write them as compact PRESENCE records,
+    /// one per channel and second the events came in, flushed every 60
+    /// seconds, or as given with --track-presence=SECONDS
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "60",
+        conflicts_with = "dont_filter"
+    )]
+    track_presence: Option<u64>,
+    /// What to do when writing a message to the output fails
+    #[arg(long, value_enum, default_value_t = WriteErrorPolicy::Abort)]
+    on_write_error: WriteErrorPolicy,
+    /// The file where messages that failed to be written are appended to,
+    /// when --on-write-error is dead-letter. A FAILOVER record with the error
+    /// is written to it when this starts, and a FALLBACK record with the time
+    /// span goes to the main output once writing there works again
+    #[arg(long, required_if_eq("on_write_error", "dead-letter"))]
+    dead_letter: Option<PathBuf>,
 }
 
-fn connect(args: &Args) -> Result<TcpStream> {
-    let addr = ("irc.chat.twitch.tv", 6697);
-    let stream = TcpStream::connect(addr)?;
-    let mut stream = stream.into_tls(addr.0, TLSConfig::default())?;
+impl Args {
+    fn uses_channel_id(&self) -> bool {
+        matches!(&self.output, Some(Some(path)) if path.to_string_lossy().contains("{channel_id}"))
+    }
+
+    fn is_twitch(&self) -> bool {
+        self.server == "irc.chat.twitch.tv" || self.server.ends_with(".twitch.tv")
+    }
 
-    let pass = args.pass.as_deref().unwrap_or("none");
+    fn twitch_caps(&self) -> Vec<TwitchCap> {
+        let mut caps = match &self.twitch_caps {
+            Some(caps) => caps.clone(),
+            None if self.dont_filter => {
+                vec![TwitchCap::Tags, TwitchCap::Commands, TwitchCap::Membership]
+            }
+            None => vec![TwitchCap::Tags, TwitchCap::Commands],
+        };
+        if self.track_presence.is_some() && !caps.contains(&TwitchCap::Membership) {
+            caps.push(TwitchCap::Membership);
+        }
+        caps
+    }
+}
+
+fn connect(args: &Args, channels: &Channels) -> Result<TcpStream> {
+    let port = args.port.unwrap_or(if args.no_tls { 6667 } else { 6697 });
+    let stream = TcpStream::connect((&*args.server, port))?;
+    let mut stream = if args.no_tls {
+        stream
+    } else {
+        stream.into_tls(&args.server, TLSConfig::default())?
+    };
+
+    let twitch = args.is_twitch();
     let nick = args.nick.as_deref().unwrap_or("justinfan1337");
 
-    write!(stream, "PASS {pass}\r\n")?;
+    // twitch wants a password even for the anonymous users
+    match (&args.pass, twitch) {
+        (Some(pass), _) => write!(stream, "PASS {pass}\r\n")?,
+        (None, true) => write!(stream, "PASS none\r\n")?,
+        (None, false) => {}
+    }
     write!(stream, "NICK {nick}\r\n")?;
-    write!(stream, "CAP REQ :twitch.tv/tags\r\n")?;
-    write!(stream, "CAP REQ :twitch.tv/commands\r\n")?;
-    if args.dont_filter {
-        write!(stream, "CAP REQ :twitch.tv/membership\r\n")?;
+    if twitch {
+        for cap in args.twitch_caps() {
+            write!(stream, "CAP REQ :{}\r\n", cap.name())?;
+        }
+    } else {
+        write!(stream, "USER {nick} 0 * :{nick}\r\n")?;
     }
-    for channel in &args.channels {
-        let channel = channel.to_ascii_lowercase();
-        write!(stream, "JOIN #{channel}\r\n")?;
+    for cap in &args.cap {
+        write!(stream, "CAP REQ :{cap}\r\n")?;
+    }
+    // a real irc server holds the registration until the negotiation ends
+    if !twitch && !args.cap.is_empty() {
+        write!(stream, "CAP END\r\n")?;
+    }
+    // and answers the joins before 001 with 451 ERR_NOTREGISTERED, the
+    // read loop joins on the welcome instead
+    if twitch {
+        join_all(&mut stream, channels)?;
     }
 
     Ok(stream)
 }
 
-const IGNORED_CMDS: &[&str] = &[
-    "366", "001", "002", "003", "004", "375", "372", "376", "CAP", "353",
-];
-
-fn run(args: &Args, backoff: &mut Duration) -> Result<()> {
-    let mut output: Box<dyn Write> = match &args.output {
-        None => Box::new(std::io::stdout()),
-        Some(output) => Box::new(FileRotate::new(
-            output.clone().unwrap_or_else(|| "twitch.log".into()),
-            AppendCount::new(usize::MAX),
-            ContentLimit::BytesSurpassed(args.rotation_limit.unwrap_or(1 << 27 /* 128 MiB */)),
-            Compression::OnRotate(0),
-            None,
-        )),
-    };
+fn join_all(stream: &mut impl Write, channels: &Channels) -> Result<()> {
+    for channel in channels.all() {
+        write!(stream, "JOIN #{channel}\r\n")?;
+    }
+    Ok(())
+}
+
+/// How often the reads time out to let the periodic tasks run
+const TICK: Duration = Duration::from_secs(1);
 
-    let mut reader = BufReader::new(connect(args)?);
+/// The state that outlives reconnects
+struct State {
+    stats: Stats,
+    channels: Channels,
+    pipeline: Pipeline,
+    checkpoint: Option<Checkpoint>,
+    raw_tap: Option<File>,
+    /// Only for Twitch with the commands capability, otherwise there is no
+    /// ROOMSTATE
+    join_check: Option<JoinCheck>,
+    /// For --max-messages and --max-duration
+    written: u64,
+    started: Instant,
+    /// When the last line was received from the server
+    last_read: Option<DateTime<Utc>>,
+    lease: Option<Lease>,
+    statsd: Option<Statsd>,
+    channel_ids: ChannelIds,
+}
+
+fn run(args: &Args, backoff: &mut Duration, state: &mut State) -> Result<()> {
+    let mut output = is_leader(state)
+        .then(|| Output::open(args, &state.channel_ids))
+        .transpose()?;
+
+    let stream = connect(args, &state.channels)?;
+    stream.set_read_timeout(Some(TICK))?;
+    let mut reader = BufReader::new(stream);
+    let mut keepalive = Keepalive::new();
+    let mut registered = args.is_twitch();
+    if let Some(join_check) = &mut state.join_check {
+        for channel in state.channels.all() {
+            join_check.joining(channel);
+        }
+    }
 
     // reset backoff after successful connection
     // kinda cringe that this is basically a callback, but oh well, it works
     *backoff = Duration::ZERO;
 
-    let mut buffer = String::with_capacity(4096);
-    while reader.read_line(&mut buffer)? != 0 {
+    match (state.last_read, &mut output) {
+        (Some(start), Some(output)) if args.mark_gaps => write_gaps(state, output, start)?,
+        _ => {}
+    }
+
+    let mut buffer = Vec::with_capacity(4096);
+    loop {
+        tick(args, state, &mut output)?;
+        if limit_reached(args, state) {
+            break;
+        }
+        keepalive.check(reader.get_mut())?;
+
+        // the channels file changes before registration are joined with the
+        // rest on the welcome
+        let (join, part) = if registered {
+            state.channels.reload()
+        } else {
+            Default::default()
+        };
+        for channel in join {
+            write!(reader.get_mut(), "JOIN #{channel}\r\n")?;
+            if let Some(join_check) = &mut state.join_check {
+                join_check.joining(&channel);
+            }
+        }
+        for channel in part {
+            write!(reader.get_mut(), "PART #{channel}\r\n")?;
+            if let Some(join_check) = &mut state.join_check {
+                join_check.parting(&channel);
+            }
+        }
+
+        // the read times out every TICK so that the periodic stuff above
+        // runs even when chat is quiet, partial lines stay in the buffer
+        match reader.read_until(b'\n', &mut buffer) {
+            Ok(0) => break,
+            Ok(_) => {
+                state.last_read = Some(Utc::now());
+                keepalive.read();
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e.into()),
+        }
+        if let Some(tap) = &mut state.raw_tap {
+            tap.write_all(&buffer)?;
+        }
         buffer.truncate(buffer.len().saturating_sub(2)); // strip crlf
-        let mut msg = Message::parse(&buffer);
+        let line = decode(&buffer);
+        let mut msg = Message::parse(&line);
 
+        if let Some(join_check) = &mut state.join_check {
+            join_check.record(&msg);
+        }
+        if msg.command == "001" && !registered {
+            join_all(reader.get_mut(), &state.channels)?;
+            registered = true;
+        }
         if msg.command == "PING" {
             let reply = msg.params.first().unwrap_or(&"");
             write!(reader.get_mut(), "PONG :{reply}\r\n")?;
-        } else if args.dont_filter || !IGNORED_CMDS.contains(&msg.command) {
-            compress(&mut msg);
-            msg.write(&mut output)?;
-            writeln!(output)?;
+        } else if let Some(rtt) = keepalive.pong(&msg) {
+            state.stats.ping(rtt);
+            if let Some(statsd) = &mut state.statsd {
+                statsd.ping(rtt);
+            }
+        } else {
+            process(state, &mut output, &mut msg)?;
         }
         drop(msg);
+        drop(line);
         buffer.clear();
     }
+    // otherwise the pending stuff goes to the output of the next connection
+    match &mut output {
+        Some(output) if limit_reached(args, state) => {
+            state.pipeline.finish(output)?;
+            output.sync();
+        }
+        _ => {}
+    }
+    if let Some(checkpoint) = &mut state.checkpoint {
+        checkpoint.save()?;
+    }
     Ok(())
 }
 
-fn compress(msg: &mut Message) {
-    // it's only twitch logins, irc user/host are redundant
-    let nick = match msg.prefix {
-        None => "",
-        Some(ref mut prefix) => {
-            if prefix.host.is_some_and(|h| h.ends_with(".tmi.twitch.tv")) {
-                prefix.host = None;
-                prefix.user = None;
-            }
-            prefix.nick
+fn open_log(path: &Path) -> Result<Box<dyn BufRead>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    // rotated logs are gzipped
+    Ok(if path.extension().is_some_and(|ext| ext == "gz") {
+        Box::new(BufReader::new(GzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    })
+}
+
+/// Archive already received IRC lines instead of connecting anywhere.
+/// With a speed given, the original pacing (sped up by it) is kept
+/// according to the tmi-sent-ts tags, to replay the archived logs
+fn passthrough(
+    args: &Args,
+    mut reader: impl BufRead,
+    speed: Option<f64>,
+    state: &mut State,
+) -> Result<()> {
+    let mut output = is_leader(state)
+        .then(|| Output::open(args, &state.channel_ids))
+        .transpose()?;
+
+    let start = Instant::now();
+    let mut first_ts: Option<u64> = None;
+    let mut buffer = Vec::with_capacity(4096);
+    while reader.read_until(b'\n', &mut buffer)? != 0 {
+        tick(args, state, &mut output)?;
+        if limit_reached(args, state) {
+            break;
+        }
+        if let Some(tap) = &mut state.raw_tap {
+            tap.write_all(&buffer)?;
         }
-    };
 
-    // the absolute majority of commands are PRIVMSG so we "compress" only those
-    if msg.command != "PRIVMSG" {
-        return;
-    }
-    msg.tags.retain_mut(|(k, v)| {
-        // room-id: ROOMSTATE gives room id for channel, and messages have channels
-        // client-nonce: useless nonce that takes up 46 bytes total
-        // emotes: they are still in the text, and we wont get extra metadata
-        // for 7tv/ffz/bttv/etc ones anyway
-        // (emotes tag only contains byteranges and emote cdn ids)
-        if k == &"room-id" || k == &"client-nonce" || k == &"emotes" {
-            return false;
-        }
-        // remove the display-name if it does nothing
-        // (if it needed escaping it's not equal to the nick lol)
-        if k == &"display-name" && nick == v.0 {
-            return false;
-        }
-
-        // yep save some bytes by base64-ing the message uuids lol
-        // (reply stuff for consistency)
-        if k == &"id" || k == &"reply-parent-msg-id" || k == &"reply-thread-parent-msg-id" {
-            if let Ok(uuid) = uuid::Uuid::parse_str(&v.0) {
-                v.0 = Cow::Owned(STANDARD_NO_PAD.encode(uuid.into_bytes()))
+        let line = decode(&buffer);
+        let mut msg = Message::parse(line.trim_end_matches(['\r', '\n']));
+        let ts = msg.tag("tmi-sent-ts").and_then(|ts| ts.0.parse().ok());
+        if let (Some(ts), Some(speed)) = (ts, speed) {
+            let first = *first_ts.get_or_insert(ts);
+            let offset = Duration::from_millis(ts.saturating_sub(first)).div_f64(speed);
+            if let Some(wait) = (start + offset).checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
             }
         }
+        if msg.command != "PING" {
+            process(state, &mut output, &mut msg)?;
+        }
+        drop(msg);
+        drop(line);
+        buffer.clear();
+    }
+    if let Some(output) = &mut output {
+        state.pipeline.finish(output)?;
+        output.sync();
+    }
+    // the input can easily end before the first report is due
+    if args.dry_run {
+        state.stats.report();
+    }
+    if let Some(checkpoint) = &mut state.checkpoint {
+        checkpoint.save()?;
+    }
+    Ok(())
+}
+
+/// Write a GAP record for every channel, so that the time we were
+/// disconnected is not mistaken for a quiet chat
+fn write_gaps(state: &State, output: &mut Output, start: DateTime<Utc>) -> Result<()> {
+    let start = irc::server_time(start);
+    let end = irc::server_time(Utc::now());
+    for channel in state.channels.all() {
+        let channel = format!("#{channel}");
+        output.write(&Message {
+            tags: vec![
+                ("gap-start", TagValue(Cow::Borrowed(&start))),
+                ("gap-end", TagValue(Cow::Borrowed(&end))),
+            ],
+            prefix: None,
+            command: "GAP",
+            params: smallvec![&*channel],
+        })?;
+    }
+    Ok(())
+}
+
+/// Invalid UTF-8 gets replaced instead of losing the whole line to it
+fn decode(raw: &[u8]) -> Cow<'_, str> {
+    let line = String::from_utf8_lossy(raw);
+    if let Cow::Owned(_) = &line {
+        eprintln!("replaced invalid UTF-8 in a line: {}", line.trim_end());
+    }
+    line
+}
+
+/// The periodic tasks, ran every TICK or every message, whichever is more
+/// frequent, so they have to check themselves whether they are due
+fn tick(args: &Args, state: &mut State, output: &mut Option<Output>) -> Result<()> {
+    let mut taken_over = None;
+    if let Some(lease) = &mut state.lease {
+        taken_over = lease.renew_if_due();
+        // the holder can rotate the file away under a standby, so the output
+        // is only kept open while holding the lease
+        if !lease.held() {
+            *output = None;
+        }
+    }
+    if output.is_none() && is_leader(state) {
+        let output = output.insert(Output::open(args, &state.channel_ids)?);
+        // a standby doesn't know when exactly the holder stopped writing
+        if let Some(start) = taken_over.filter(|_| args.mark_gaps) {
+            write_gaps(state, output, start)?;
+        }
+    }
+
+    if let Some(output) = output {
+        output.sync_if_due();
+        state.pipeline.tick(output)?;
+    }
+    state.stats.report_if_due();
+    if let Some(statsd) = &mut state.statsd {
+        statsd.flush_if_due();
+    }
+    if let Some(join_check) = &mut state.join_check {
+        join_check.warn_if_due();
+    }
+    if let Some(checkpoint) = &mut state.checkpoint {
+        checkpoint.save_if_due()?;
+    }
+    Ok(())
+}
+
+fn is_leader(state: &State) -> bool {
+    !matches!(&state.lease, Some(lease) if !lease.held())
+}
+
+fn limit_reached(args: &Args, state: &State) -> bool {
+    args.max_messages.is_some_and(|max| state.written >= max)
+        || args
+            .max_duration
+            .is_some_and(|max| state.started.elapsed() >= Duration::from_secs(max))
+}
 
-        // cleanup all the tags whose absence and empty value or 0 are equivalent
-        // (@badge-info=;color=;emotes=;first-msg=0;flags=;mod=0;returning-chatter=0;subscriber=0;turbo=0;user-type=)
-        // etc
-        !v.0.is_empty() && v.0 != "0"
-    });
+/// Everything that happens to a message after it was read, regardless of
+/// where it was read from
+fn process(state: &mut State, output: &mut Option<Output>, msg: &mut Message) -> Result<()> {
+    // a standby without the lease
+    let Some(output) = output else {
+        return Ok(());
+    };
+    if state.pipeline.process(msg) == Action::Drop {
+        return Ok(());
+    }
+    let written = output.write(msg)?;
+    if written == 0 {
+        return Ok(());
+    }
+    state.written += 1;
+    if let Some(channel) = msg.channel() {
+        state.stats.record(channel, written);
+        if let Some(statsd) = &mut state.statsd {
+            statsd.record(channel, written);
+        }
+    }
+    if let Some(checkpoint) = &mut state.checkpoint {
+        checkpoint.record(msg);
+    }
+    Ok(())
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
+    match &args.command {
+        Some(Command::Completions { shell }) => {
+            let mut cmd = Args::command();
+            let name = cmd.get_name().to_owned();
+            clap_complete::generate(*shell, &mut cmd, name, &mut std::io::stdout());
+            return Ok(());
+        }
+        Some(Command::Repair { file, output }) => {
+            return repair::repair(file, output.as_deref());
+        }
+        Some(Command::Erase { user, files }) => {
+            let mut total = 0;
+            for file in files {
+                let erased = erase::erase(file, user)?;
+                println!("{}: erased {erased} messages", file.display());
+                total += erased;
+            }
+            println!("erased {total} messages of {user} in total");
+            return Ok(());
+        }
+        Some(Command::Import { source }) => {
+            let mut stdout = std::io::stdout().lock();
+            match source {
+                ImportSource::Chatterino { files, timezone } => {
+                    for file in files {
+                        import::chatterino(file, *timezone, &mut stdout)?;
+                    }
+                }
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let channels = Channels::new(&args.channels, args.channels_file.clone())?;
+    let stats_interval = match args.stats_interval {
+        None if args.dry_run => Some(1),
+        minutes => minutes,
+    };
+    let stats_interval = stats_interval.map(|m| Duration::from_secs(m * 60));
+    let mut state = State {
+        stats: Stats::new(channels.all(), stats_interval),
+        channels,
+        pipeline: Pipeline::new(&args),
+        checkpoint: args.checkpoint.clone().map(Checkpoint::load).transpose()?,
+        join_check: (args.is_twitch() && args.twitch_caps().contains(&TwitchCap::Commands))
+            .then(JoinCheck::default),
+        raw_tap: match &args.raw_tap {
+            Some(path) => Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("failed to open {}", path.display()))?,
+            ),
+            None => None,
+        },
+        written: 0,
+        started: Instant::now(),
+        last_read: None,
+        lease: args.lease.clone().map(Lease::new),
+        channel_ids: ChannelIds::default(),
+        statsd: match &args.statsd {
+            Some(address) => Some(
+                Statsd::connect(address)
+                    .with_context(|| format!("failed to connect to statsd at {address}"))?,
+            ),
+            None => None,
+        },
+    };
+
+    if let Some(path) = &args.replay {
+        if args.speed <= 0.0 {
+            bail!("--speed has to be positive");
+        }
+        return passthrough(&args, open_log(path)?, Some(args.speed), &mut state);
+    }
+    if args.stdin {
+        return passthrough(&args, std::io::stdin().lock(), None, &mut state);
+    }
+
     let mut backoff = Duration::ZERO;
     loop {
-        let result = run(&args, &mut backoff);
+        let result = run(&args, &mut backoff, &mut state);
+        if limit_reached(&args, &state) {
+            return result;
+        }
         eprintln!(
-            "disconnected from twitch, waiting for {} seconds and retrying, result was {result:?}",
+            "disconnected from {}, waiting for {} seconds and retrying, result was {result:?}",
+            args.server,
             backoff.as_secs()
         );
         if backoff == Duration::ZERO {
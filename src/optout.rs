@@ -0,0 +1,64 @@
+use crate::{
+    erase,
+    irc::Message,
+    output::Output,
+    processor::{Action, Processor},
+};
+use anyhow::Result;
+use std::{collections::HashSet, path::PathBuf, time::SystemTime};
+
+/// The users (logins or ids, one per line) whose messages are never
+/// written, the file is re-read whenever it changes
+pub struct OptOut {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    users: HashSet<String>,
+}
+
+impl OptOut {
+    pub fn new(path: PathBuf) -> Self {
+        let mut opt_out = Self {
+            path,
+            modified: None,
+            users: HashSet::new(),
+        };
+        opt_out.reload();
+        opt_out
+    }
+
+    fn reload(&mut self) {
+        let modified = self.path.metadata().and_then(|m| m.modified()).ok();
+        if modified.is_none() || modified == self.modified {
+            return;
+        }
+        match std::fs::read_to_string(&self.path) {
+            Ok(contents) => {
+                self.users = contents
+                    .lines()
+                    .map(|line| line.trim().to_ascii_lowercase())
+                    .filter(|line| !line.is_empty())
+                    .collect();
+                self.modified = modified;
+            }
+            Err(e) => eprintln!("failed to read {}: {e}", self.path.display()),
+        }
+    }
+}
+
+impl Processor for OptOut {
+    fn process(&mut self, msg: &mut Message) -> Action {
+        // twitch sends the logins in lowercase already
+        if erase::users(msg)
+            .iter()
+            .any(|user| self.users.contains(*user))
+        {
+            return Action::Drop;
+        }
+        Action::Keep
+    }
+
+    fn tick(&mut self, _output: &mut Output) -> Result<()> {
+        self.reload();
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use crate::{
+    influx,
+    irc::{self, Message, TagValue},
+    pretty,
+    syslog::Syslog,
+    template::{self, ChannelIds, Templated},
+    timezone::Timezone,
+    Args,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use file_rotate::{suffix::AppendCount, FileRotate};
+use smallvec::smallvec;
+use std::{
+    borrow::Cow,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    net::TcpStream,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    /// Raw IRC lines, slightly compressed
+    Irc,
+    /// Human-readable `HH:MM:SS #channel <name> message` lines
+    Pretty,
+    /// InfluxDB line protocol points with message counts, bits, sub months
+    /// etc, but no text
+    Influx,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum WriteErrorPolicy {
+    /// Treat it like a disconnect: reopen the output and reconnect
+    Abort,
+    /// Drop the message and keep going, counting the dropped ones
+    Skip,
+    /// Write the message to the --dead-letter file instead
+    DeadLetter,
+}
+
+enum Sink {
+    Stream(Box<dyn Write>),
+    /// Kept apart from the other streams for the path, to fsync it
+    File(PathBuf, FileRotate<AppendCount>),
+    Syslog(Syslog),
+    Templated(Templated),
+}
+
+struct Formatter {
+    format: OutputFormat,
+    /// Add the user as a tag to the influx points
+    influx_user: bool,
+    color: bool,
+    timezone: Timezone,
+}
+
+impl Formatter {
+    fn format(&self, msg: &Message, line: &mut Vec<u8>) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Irc => msg.write(&mut *line)?,
+            OutputFormat::Pretty => pretty::write(msg, self.color, self.timezone, &mut *line)?,
+            OutputFormat::Influx => influx::write(msg, self.influx_user, &mut *line)?,
+        }
+        line.push(b'\n');
+        Ok(())
+    }
+}
+
+pub struct Output {
+    sink: Sink,
+    formatter: Formatter,
+    on_error: WriteErrorPolicy,
+    dead_letter: Option<File>,
+    /// Since when the messages are going to the dead letter file
+    failed_since: Option<DateTime<Utc>>,
+    fsync: Option<Duration>,
+    synced: Instant,
+    skipped: usize,
+    line: Vec<u8>,
+}
+
+impl Output {
+    pub fn open(args: &Args, channel_ids: &ChannelIds) -> Result<Self> {
+        let rotation_limit = args.rotation_limit.unwrap_or(1 << 27 /* 128 MiB */);
+        let sink = match (&args.output, &args.syslog) {
+            _ if args.dry_run => Sink::Stream(Box::new(io::sink())),
+            _ if args.ilp.is_some() => {
+                let addr = args.ilp.as_deref().unwrap_or_default();
+                let stream = TcpStream::connect(addr)
+                    .with_context(|| format!("failed to connect to {addr}"))?;
+                Sink::Stream(Box::new(stream))
+            }
+            (_, Some(url)) => Sink::Syslog(Syslog::connect(url)?),
+            (None, None) => Sink::Stream(Box::new(std::io::stdout())),
+            (Some(output), None) => {
+                let path = output.clone().unwrap_or_else(|| "twitch.log".into());
+                match Templated::parse(&path, args.timezone, rotation_limit, channel_ids.clone())? {
+                    Some(templated) => Sink::Templated(templated),
+                    None => {
+                        let file = template::rotating_file(&path, rotation_limit);
+                        Sink::File(path, file)
+                    }
+                }
+            }
+        };
+        let dead_letter = match &args.dead_letter {
+            Some(path) if args.on_write_error == WriteErrorPolicy::DeadLetter => {
+                Some(OpenOptions::new().create(true).append(true).open(path)?)
+            }
+            _ => None,
+        };
+        Ok(Self {
+            sink,
+            formatter: Formatter {
+                format: if args.ilp.is_some() {
+                    OutputFormat::Influx
+                } else {
+                    args.format
+                },
+                influx_user: args.ilp.is_some(),
+                color: args.color,
+                timezone: args.timezone,
+            },
+            on_error: args.on_write_error,
+            dead_letter,
+            failed_since: None,
+            fsync: args.fsync.map(Duration::from_secs),
+            synced: Instant::now(),
+            skipped: 0,
+            line: Vec::with_capacity(4096),
+        })
+    }
+
+    /// Write out the message, returns how many bytes made it to the output
+    /// (zero if the message was skipped or dead-lettered)
+    pub fn write(&mut self, msg: &Message) -> Result<usize> {
+        // format the whole line first so that a failed write never
+        // leaves half of it in the output
+        self.line.clear();
+        self.formatter.format(msg, &mut self.line)?;
+
+        let result = match &mut self.sink {
+            Sink::Stream(stream) => stream.write_all(&self.line),
+            Sink::File(_, file) => file.write_all(&self.line),
+            Sink::Syslog(syslog) => syslog.send(msg, &self.line[..self.line.len() - 1]),
+            Sink::Templated(templated) => templated.write(msg, &self.line),
+        };
+        let Err(e) = result else {
+            let written = self.line.len();
+            if let Some(start) = self.failed_since.take() {
+                self.write_fallback(start)?;
+            }
+            return Ok(written);
+        };
+        match (self.on_error, &mut self.dead_letter) {
+            (WriteErrorPolicy::Skip, _) => {
+                self.skipped += 1;
+                eprintln!(
+                    "failed to write a message, skipped {} so far: {e}",
+                    self.skipped
+                );
+            }
+            (WriteErrorPolicy::DeadLetter, Some(dead_letter)) => {
+                if self.failed_since.is_none() {
+                    eprintln!("failed to write a message, switching to the dead letter file: {e}");
+                    let now = Utc::now();
+                    self.failed_since = Some(now);
+
+                    let time = irc::server_time(now);
+                    let error = e.to_string();
+                    let mut marker = Vec::new();
+                    self.formatter.format(
+                        &Message {
+                            tags: vec![("time", TagValue(Cow::Borrowed(&time)))],
+                            prefix: None,
+                            command: "FAILOVER",
+                            params: smallvec![&*error],
+                        },
+                        &mut marker,
+                    )?;
+                    dead_letter.write_all(&marker)?;
+                }
+                dead_letter.write_all(&self.line)?;
+            }
+            _ => return Err(e.into()),
+        }
+        Ok(0)
+    }
+
+    /// Make sure that what was written so far survives a power loss, for
+    /// the file outputs, every --fsync seconds
+    pub fn sync_if_due(&mut self) {
+        match self.fsync {
+            Some(interval) if self.synced.elapsed() >= interval => self.sync(),
+            _ => {}
+        }
+    }
+
+    /// Sync right away when --fsync is given, for when the archiver stops.
+    /// A failed sync only gets logged, the messages are written already
+    pub fn sync(&mut self) {
+        if self.fsync.is_none() {
+            return;
+        }
+        self.synced = Instant::now();
+
+        // FileRotate doesn't give out its file, but fsync works through any
+        // descriptor of the same file
+        let paths = match &self.sink {
+            Sink::File(path, _) => vec![path],
+            Sink::Templated(templated) => templated.paths().collect(),
+            Sink::Stream(_) | Sink::Syslog(_) => vec![],
+        };
+        for path in paths {
+            if let Err(e) = File::open(path).and_then(|file| file.sync_all()) {
+                eprintln!("failed to fsync {}: {e}", path.display());
+            }
+        }
+        if let Some(dead_letter) = &self.dead_letter {
+            if let Err(e) = dead_letter.sync_all() {
+                eprintln!("failed to fsync the dead letter file: {e}");
+            }
+        }
+    }
+
+    /// Mark the span during which the messages went to the dead letter file
+    /// instead, like the GAP records do for disconnects
+    fn write_fallback(&mut self, start: DateTime<Utc>) -> Result<()> {
+        eprintln!("writing messages works again, switching back from the dead letter file");
+        let start = irc::server_time(start);
+        let end = irc::server_time(Utc::now());
+        self.write(&Message {
+            tags: vec![
+                ("fallback-start", TagValue(Cow::Borrowed(&start))),
+                ("fallback-end", TagValue(Cow::Borrowed(&end))),
+            ],
+            prefix: None,
+            command: "FALLBACK",
+            params: smallvec![],
+        })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{
+    irc::{self, Message, TagValue},
+    output::Output,
+    processor::{Action, Processor},
+};
+use anyhow::Result;
+use chrono::{DateTime, SubsecRound, Utc};
+use smallvec::smallvec;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+/// JOIN/PART events batched into one compact record per channel and second
+/// they came in, written as `@time=... PRESENCE #channel :+joined -parted`
+pub struct Presence {
+    events: BTreeMap<(DateTime<Utc>, String), String>,
+    interval: Duration,
+    since: Instant,
+}
+
+impl Presence {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            events: BTreeMap::new(),
+            interval,
+            since: Instant::now(),
+        }
+    }
+}
+
+impl Processor for Presence {
+    /// The events are dropped, they are batched into the next record
+    fn process(&mut self, msg: &mut Message) -> Action {
+        let sign = match msg.command {
+            "JOIN" => '+',
+            "PART" => '-',
+            _ => return Action::Keep,
+        };
+        if let (Some(channel), Some(prefix)) = (msg.channel(), &msg.prefix) {
+            let second = Utc::now().trunc_subsecs(0);
+            let key = (second, channel.to_owned());
+            let events = self.events.entry(key).or_default();
+            if !events.is_empty() {
+                events.push(' ');
+            }
+            events.push(sign);
+            events.push_str(prefix.nick);
+        }
+        Action::Drop
+    }
+
+    fn tick(&mut self, output: &mut Output) -> Result<()> {
+        if self.since.elapsed() < self.interval {
+            return Ok(());
+        }
+        self.finish(output)
+    }
+
+    fn finish(&mut self, output: &mut Output) -> Result<()> {
+        self.since = Instant::now();
+
+        for ((second, channel), events) in std::mem::take(&mut self.events) {
+            let time = irc::server_time(second);
+            let channel = format!("#{channel}");
+            let msg = Message {
+                tags: vec![("time", TagValue(Cow::Borrowed(&time)))],
+                prefix: None,
+                command: "PRESENCE",
+                params: smallvec![&*channel, &*events],
+            };
+            output.write(&msg)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{irc::Message, timezone::Timezone};
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+};
+
+/// IRC-style short prefixes for the badges worth seeing at a glance
+const BADGE_PREFIXES: &[(&str, char)] = &[
+    ("broadcaster", '~'),
+    ("moderator", '@'),
+    ("vip", '!'),
+    ("subscriber", '+'),
+];
+
+pub fn write<W: Write>(msg: &Message, color: bool, tz: Timezone, mut w: W) -> io::Result<()> {
+    write!(w, "{}", tz.format(msg.sent_time(), "%H:%M:%S"))?;
+
+    let mut params = &msg.params[..];
+    if let Some((channel, rest)) = params.split_first() {
+        if channel.starts_with('#') {
+            write!(w, " {channel}")?;
+            params = rest;
+        }
+    }
+
+    // /me messages are wrapped in CTCP ACTION, show those as `* name text`
+    let action = match (msg.command, params.split_last()) {
+        ("PRIVMSG", Some((text, rest))) => text
+            .strip_prefix("\x01ACTION ")
+            .map(|text| (text.strip_suffix('\x01').unwrap_or(text), rest)),
+        _ => None,
+    };
+
+    match msg.command {
+        "PRIVMSG" | "WHISPER" => {
+            let nick = msg.prefix.as_ref().map_or("", |p| p.nick);
+            // compress() drops the display-name when it's the same as the nick
+            let name = msg
+                .tag("display-name")
+                .map_or(Cow::Borrowed(nick), |name| name.unescape());
+
+            write!(w, "{}", if action.is_some() { " * " } else { " <" })?;
+            if let Some(badges) = msg.tag("badges") {
+                for (badge, prefix) in BADGE_PREFIXES {
+                    let has_badge = badges
+                        .0
+                        .split(',')
+                        .any(|b| b.split('/').next() == Some(badge));
+                    if has_badge {
+                        write!(w, "{prefix}")?;
+                    }
+                }
+            }
+            match msg
+                .tag("color")
+                .and_then(|c| parse_color(&c.0))
+                .filter(|_| color)
+            {
+                Some((r, g, b)) => write!(w, "\x1b[38;2;{r};{g};{b}m{name}\x1b[0m")?,
+                None => write!(w, "{name}")?,
+            }
+            if action.is_none() {
+                write!(w, ">")?;
+            }
+            // whispers have the recipient instead of a channel
+            if msg.command == "WHISPER" {
+                if let Some((to, rest)) = params.split_first() {
+                    write!(w, " -> {to}:")?;
+                    params = rest;
+                }
+            }
+        }
+        "USERNOTICE" => {
+            let system_msg = msg.tag("system-msg").map(|m| m.unescape());
+            write!(w, " * {}", system_msg.as_deref().unwrap_or(""))?;
+        }
+        cmd => write!(w, " {cmd}")?,
+    }
+    for param in action.map_or(params, |(_, rest)| rest) {
+        write!(w, " {param}")?;
+    }
+    if let Some((text, _)) = action {
+        write!(w, " {text}")?;
+    }
+    Ok(())
+}
+
+fn parse_color(color: &str) -> Option<(u8, u8, u8)> {
+    let hex = color.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some((channel(0)?, channel(2)?, channel(4)?))
+}
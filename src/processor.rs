@@ -0,0 +1,204 @@
+use crate::{
+    irc::{self, Message, TagValue},
+    optout::OptOut,
+    output::Output,
+    presence::Presence,
+    sample::Sampler,
+    seen::SeenUsers,
+    Args,
+};
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use chrono::Utc;
+use std::{borrow::Cow, time::Duration};
+use uuid::Uuid;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Keep,
+    /// The message is not written, and the following processors don't see it
+    Drop,
+}
+
+/// A stage between parsing a message and writing it out
+pub trait Processor {
+    fn process(&mut self, msg: &mut Message) -> Action;
+
+    /// Called every TICK, for processors that write something on their own
+    fn tick(&mut self, _output: &mut Output) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once when stopping, to write whatever is still pending
+    fn finish(&mut self, _output: &mut Output) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The processors that the arguments ask for, in the order they run
+pub struct Pipeline(Vec<Box<dyn Processor>>);
+
+impl Pipeline {
+    pub fn new(args: &Args) -> Self {
+        let mut processors: Vec<Box<dyn Processor>> = Vec::new();
+        // first, so that nothing else (like the presence batches) sees them
+        if let Some(path) = &args.opt_out {
+            processors.push(Box::new(OptOut::new(path.clone())));
+        }
+        if let Some(interval) = args.track_presence {
+            processors.push(Box::new(Presence::new(Duration::from_secs(interval))));
+        }
+        if !args.dont_filter {
+            processors.push(Box::new(IgnoreCommands));
+        }
+        if !args.sample.is_empty() {
+            processors.push(Box::new(Sampler::new(&args.sample)));
+        }
+        if let Some(dir) = &args.seen_users {
+            processors.push(Box::new(SeenUsers::new(dir.clone())));
+        }
+        if args.receive_time {
+            processors.push(Box::new(ReceiveTime));
+        }
+        let mut keep_tag = args.keep_tag.clone();
+        // so that the path stays right for the replays of the archive too
+        if args.uses_channel_id() {
+            keep_tag.push("room-id".to_owned());
+        }
+        processors.push(Box::new(Compress {
+            drop_tag: args.drop_tag.clone(),
+            keep_tag,
+            no_compress: args.no_compress,
+        }));
+        Self(processors)
+    }
+
+    pub fn process(&mut self, msg: &mut Message) -> Action {
+        for processor in &mut self.0 {
+            if processor.process(msg) == Action::Drop {
+                return Action::Drop;
+            }
+        }
+        Action::Keep
+    }
+
+    pub fn tick(&mut self, output: &mut Output) -> Result<()> {
+        for processor in &mut self.0 {
+            processor.tick(output)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self, output: &mut Output) -> Result<()> {
+        for processor in &mut self.0 {
+            processor.finish(output)?;
+        }
+        Ok(())
+    }
+}
+
+const IGNORED_CMDS: &[&str] = &[
+    "366", "001", "002", "003", "004", "375", "372", "376", "CAP", "353",
+];
+
+/// Drops the connection noise that is useless in an archive
+struct IgnoreCommands;
+
+impl Processor for IgnoreCommands {
+    fn process(&mut self, msg: &mut Message) -> Action {
+        if IGNORED_CMDS.contains(&msg.command) {
+            Action::Drop
+        } else {
+            Action::Keep
+        }
+    }
+}
+
+/// Adds a time tag to the messages that don't have a tmi-sent-ts
+struct ReceiveTime;
+
+impl Processor for ReceiveTime {
+    fn process(&mut self, msg: &mut Message) -> Action {
+        if msg.tag("tmi-sent-ts").is_none() {
+            let now = irc::server_time(Utc::now());
+            msg.tags.push(("time", TagValue(Cow::Owned(now))));
+        }
+        Action::Keep
+    }
+}
+
+/// The tags with message uuids, which compress() base64s
+pub const ID_TAGS: &[&str] = &["id", "reply-parent-msg-id", "reply-thread-parent-msg-id"];
+
+/// Undo what compress() does to the ids, None if it's not a base64 uuid
+pub fn decode_id(id: &str) -> Option<Uuid> {
+    let bytes = STANDARD_NO_PAD.decode(id).ok()?;
+    Uuid::from_slice(&bytes).ok()
+}
+
+struct Compress {
+    drop_tag: Vec<String>,
+    keep_tag: Vec<String>,
+    no_compress: bool,
+}
+
+impl Processor for Compress {
+    fn process(&mut self, msg: &mut Message) -> Action {
+        if !self.drop_tag.is_empty() {
+            msg.tags
+                .retain(|(k, _)| !self.drop_tag.iter().any(|t| t == k));
+        }
+        if self.no_compress {
+            return Action::Keep;
+        }
+
+        // it's only twitch logins, irc user/host are redundant
+        let nick = match msg.prefix {
+            None => "",
+            Some(ref mut prefix) => {
+                if prefix.host.is_some_and(|h| h.ends_with(".tmi.twitch.tv")) {
+                    prefix.host = None;
+                    prefix.user = None;
+                }
+                prefix.nick
+            }
+        };
+
+        // the absolute majority of commands are PRIVMSG so we "compress" only those
+        if msg.command != "PRIVMSG" {
+            return Action::Keep;
+        }
+        msg.tags.retain_mut(|(k, v)| {
+            if self.keep_tag.iter().any(|t| t == k) {
+                return true;
+            }
+            // room-id: ROOMSTATE gives room id for channel, and messages have channels
+            // client-nonce: useless nonce that takes up 46 bytes total
+            // emotes: they are still in the text, and we wont get extra metadata
+            // for 7tv/ffz/bttv/etc ones anyway
+            // (emotes tag only contains byteranges and emote cdn ids)
+            if k == &"room-id" || k == &"client-nonce" || k == &"emotes" {
+                return false;
+            }
+            // remove the display-name if it does nothing
+            // (if it needed escaping it's not equal to the nick lol)
+            if k == &"display-name" && nick == v.0 {
+                return false;
+            }
+
+            // yep save some bytes by base64-ing the message uuids lol
+            // (reply stuff for consistency)
+            if ID_TAGS.contains(k) {
+                if let Ok(uuid) = Uuid::parse_str(&v.0) {
+                    v.0 = Cow::Owned(STANDARD_NO_PAD.encode(uuid.into_bytes()))
+                }
+            }
+
+            // cleanup all the tags whose absence and empty value or 0 are equivalent
+            // (@badge-info=;color=;emotes=;first-msg=0;flags=;mod=0;returning-chatter=0;subscriber=0;turbo=0;user-type=)
+            // etc
+            !v.0.is_empty() && v.0 != "0"
+        });
+        Action::Keep
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{
+    decode,
+    irc::{Message, TagValue},
+    open_log,
+    processor::{self, ID_TAGS},
+};
+use anyhow::{Context, Result};
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, BufRead, BufWriter, Write},
+    path::Path,
+};
+
+/// Rewrite an archive with the base64 ids from compress() turned back into
+/// the usual uuids, into a new file or in place
+pub fn repair(path: &Path, output: Option<&Path>) -> Result<()> {
+    let mut repaired = 0;
+    rewrite(path, output, |msg| {
+        for (k, v) in &mut msg.tags {
+            if !ID_TAGS.contains(k) {
+                continue;
+            }
+            if let Some(uuid) = processor::decode_id(&v.0) {
+                *v = TagValue(Cow::Owned(uuid.to_string()));
+                repaired += 1;
+            }
+        }
+        Edit::Keep
+    })?;
+    eprintln!("repaired {repaired} ids in {}", path.display());
+    Ok(())
+}
+
+/// What to do with a message when rewriting an archive
+pub enum Edit {
+    /// Write the message, with whatever changes were made to it
+    Keep,
+    /// Leave it out
+    Drop,
+    /// Write this line instead, for when the params have to change
+    Replace(String),
+}
+
+/// Rewrite an archive (which can be gzipped) message by message, into a new
+/// file or in place through a temp file
+pub fn rewrite(
+    path: &Path,
+    output: Option<&Path>,
+    edit: impl FnMut(&mut Message) -> Edit,
+) -> Result<()> {
+    let reader = open_log(path)?;
+
+    let mut tmp = path.to_owned().into_os_string();
+    tmp.push(".tmp");
+    let target = output.unwrap_or(tmp.as_ref());
+    let file =
+        File::create(target).with_context(|| format!("failed to create {}", target.display()))?;
+    let is_gz = |path: &Path| path.extension().is_some_and(|ext| ext == "gz");
+
+    if is_gz(output.unwrap_or(path)) {
+        let mut encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
+        rewrite_lines(reader, &mut encoder, edit)?;
+        encoder.finish()?.flush()?;
+    } else {
+        let mut writer = BufWriter::new(file);
+        rewrite_lines(reader, &mut writer, edit)?;
+        writer.flush()?;
+    }
+
+    if output.is_none() {
+        if is_gz(path) {
+            std::fs::rename(&tmp, path)?;
+        } else {
+            // a running archiver still has the current log open, and would
+            // keep writing into the old file after a rename, so the contents
+            // are copied back into the same one (it appends, so it goes on
+            // after the new end)
+            io::copy(&mut File::open(&tmp)?, &mut File::create(path)?)?;
+            std::fs::remove_file(&tmp)?;
+        }
+    }
+    Ok(())
+}
+
+fn rewrite_lines(
+    mut reader: impl BufRead,
+    mut writer: impl Write,
+    mut edit: impl FnMut(&mut Message) -> Edit,
+) -> Result<()> {
+    let mut buffer = Vec::with_capacity(4096);
+    while reader.read_until(b'\n', &mut buffer)? != 0 {
+        let line = decode(&buffer);
+        let mut msg = Message::parse(line.trim_end_matches(['\r', '\n']));
+        match edit(&mut msg) {
+            Edit::Keep => {
+                msg.write(&mut writer)?;
+                writer.write_all(b"\n")?;
+            }
+            Edit::Drop => {}
+            Edit::Replace(line) => {
+                writer.write_all(line.as_bytes())?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        drop(msg);
+        drop(line);
+        buffer.clear();
+    }
+    Ok(())
+}
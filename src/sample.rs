@@ -0,0 +1,90 @@
+use crate::{
+    irc::Message,
+    processor::{self, Action, Processor},
+};
+use std::{collections::HashMap, str::FromStr};
+use uuid::Uuid;
+
+/// A `[channel=]percent` argument, without a channel it applies to all of
+/// them
+#[derive(Clone)]
+pub struct SampleRate {
+    channel: Option<String>,
+    percent: f64,
+}
+
+impl FromStr for SampleRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (channel, percent) = match s.split_once('=') {
+            Some((channel, percent)) => {
+                let channel = channel.trim_start_matches('#').to_ascii_lowercase();
+                (Some(channel), percent)
+            }
+            None => (None, s),
+        };
+        match percent.parse() {
+            Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(Self { channel, percent }),
+            _ => Err(format!("{percent} is not a percentage from 0 to 100")),
+        }
+    }
+}
+
+/// Keeps only a percentage of the PRIVMSGs, everything else (subs, bans,
+/// etc) is always kept. Which ones are kept only depends on the message id,
+/// so the same messages are picked on every run
+pub struct Sampler {
+    default: Option<f64>,
+    channels: HashMap<String, f64>,
+}
+
+impl Sampler {
+    pub fn new(rates: &[SampleRate]) -> Self {
+        let mut default = None;
+        let mut channels = HashMap::new();
+        for rate in rates {
+            match &rate.channel {
+                Some(channel) => {
+                    channels.insert(channel.clone(), rate.percent);
+                }
+                None => default = Some(rate.percent),
+            }
+        }
+        Self { default, channels }
+    }
+}
+
+/// FNV-1a, std's hashers are not guaranteed to be the same across versions
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl Processor for Sampler {
+    fn process(&mut self, msg: &mut Message) -> Action {
+        if msg.command != "PRIVMSG" {
+            return Action::Keep;
+        }
+        let (Some(channel), Some(id)) = (msg.channel(), msg.tag("id")) else {
+            return Action::Keep;
+        };
+        let Some(percent) = self.channels.get(channel).copied().or(self.default) else {
+            return Action::Keep;
+        };
+        // compress() base64s the ids, so that has to be undone for replayed
+        // archives to be sampled the same way as the live chat
+        let uuid = Uuid::parse_str(&id.0)
+            .ok()
+            .or_else(|| processor::decode_id(&id.0));
+        let hash = match uuid {
+            Some(uuid) => hash(uuid.as_bytes()),
+            None => hash(id.0.as_bytes()),
+        };
+        if (hash % 10000) as f64 >= percent * 100.0 {
+            return Action::Drop;
+        }
+        Action::Keep
+    }
+}
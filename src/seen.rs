@@ -0,0 +1,84 @@
+use crate::{
+    irc::{Message, TagValue},
+    processor::{Action, Processor},
+    template,
+};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+};
+
+struct Channel {
+    seen: HashSet<String>,
+    /// None if the file could not be opened, then it's only kept in memory
+    file: Option<File>,
+}
+
+/// Marks the first archived message of every user in a channel with a
+/// `first-archived=1` tag. The user ids are appended to a file per channel
+/// in the directory, so that it holds across restarts
+pub struct SeenUsers {
+    dir: PathBuf,
+    channels: HashMap<String, Channel>,
+}
+
+impl SeenUsers {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            channels: HashMap::new(),
+        }
+    }
+
+    fn load(&self, channel: &str) -> io::Result<Channel> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(&*template::path_safe(channel));
+        let seen = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents.lines().map(str::to_owned).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Channel {
+            seen,
+            file: Some(file),
+        })
+    }
+}
+
+impl Processor for SeenUsers {
+    fn process(&mut self, msg: &mut Message) -> Action {
+        if msg.command != "PRIVMSG" {
+            return Action::Keep;
+        }
+        let (Some(channel), Some(user)) = (msg.channel(), msg.tag("user-id")) else {
+            return Action::Keep;
+        };
+        if !self.channels.contains_key(channel) {
+            let loaded = self.load(channel).unwrap_or_else(|e| {
+                eprintln!("failed to load the seen users of {channel}: {e}");
+                Channel {
+                    seen: HashSet::new(),
+                    file: None,
+                }
+            });
+            self.channels.insert(channel.to_owned(), loaded);
+        }
+        let state = self.channels.get_mut(channel).expect("inserted above");
+        if state.seen.contains(&*user.0) {
+            return Action::Keep;
+        }
+        if let Some(file) = &mut state.file {
+            if let Err(e) = writeln!(file, "{}", user.0) {
+                eprintln!("failed to save a seen user of {channel}: {e}");
+            }
+        }
+        state.seen.insert(user.0.to_string());
+        msg.tags
+            .push(("first-archived", TagValue(Cow::Borrowed("1"))));
+        Action::Keep
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+#[derive(Default)]
+struct ChannelStats {
+    messages: u64,
+    bytes: u64,
+}
+
+/// Per-channel throughput counters, periodically reported to stderr
+pub struct Stats {
+    channels: BTreeMap<String, ChannelStats>,
+    interval: Option<Duration>,
+    since: Instant,
+    /// The last round trip of our pings to the server
+    ping: Option<Duration>,
+}
+
+impl Stats {
+    pub fn new<'a>(
+        channels: impl IntoIterator<Item = &'a str>,
+        interval: Option<Duration>,
+    ) -> Self {
+        Self {
+            // prefill the joined channels so that silent ones show up as zeroes
+            channels: channels
+                .into_iter()
+                .map(|c| (c.to_owned(), ChannelStats::default()))
+                .collect(),
+            interval,
+            since: Instant::now(),
+            ping: None,
+        }
+    }
+
+    pub fn record(&mut self, channel: &str, bytes: usize) {
+        let stats = match self.channels.get_mut(channel) {
+            Some(stats) => stats,
+            None => self.channels.entry(channel.to_owned()).or_default(),
+        };
+        stats.messages += 1;
+        stats.bytes += bytes as u64;
+    }
+
+    pub fn ping(&mut self, rtt: Duration) {
+        self.ping = Some(rtt);
+    }
+
+    pub fn report_if_due(&mut self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        if self.since.elapsed() >= interval {
+            self.report();
+        }
+    }
+
+    pub fn report(&mut self) {
+        let elapsed = self.since.elapsed();
+        eprintln!("throughput over the last {} seconds:", elapsed.as_secs());
+        for (channel, stats) in &mut self.channels {
+            eprintln!(
+                "  #{channel}: {:.2} msg/s, {} bytes written",
+                stats.messages as f64 / elapsed.as_secs_f64(),
+                stats.bytes,
+            );
+            *stats = ChannelStats::default();
+        }
+        if let Some(ping) = self.ping {
+            eprintln!("  ping to the server: {} ms", ping.as_millis());
+        }
+        self.since = Instant::now();
+    }
+}
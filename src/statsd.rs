@@ -0,0 +1,101 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    net::UdpSocket,
+    time::{Duration, Instant},
+};
+
+/// How often the counters are sent, statsd sums them up anyway
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Keep the datagrams under the usual MTU
+const MAX_PACKET: usize = 1400;
+
+#[derive(Default)]
+struct Counters {
+    messages: u64,
+    bytes: u64,
+}
+
+/// The per-channel message and byte counters (and the ping round trip) sent
+/// to a statsd server over UDP, as `twitch_archiver.<channel>.messages` etc
+pub struct Statsd {
+    socket: UdpSocket,
+    channels: BTreeMap<String, Counters>,
+    ping: Option<Duration>,
+    flushed: Instant,
+}
+
+impl Statsd {
+    pub fn connect(address: &str) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(address)?;
+        Ok(Self {
+            socket,
+            channels: BTreeMap::new(),
+            ping: None,
+            flushed: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, channel: &str, bytes: usize) {
+        let counters = match self.channels.get_mut(channel) {
+            Some(counters) => counters,
+            None => self.channels.entry(channel.to_owned()).or_default(),
+        };
+        counters.messages += 1;
+        counters.bytes += bytes as u64;
+    }
+
+    pub fn ping(&mut self, rtt: Duration) {
+        self.ping = Some(rtt);
+    }
+
+    pub fn flush_if_due(&mut self) {
+        if self.flushed.elapsed() < FLUSH_INTERVAL {
+            return;
+        }
+        self.flushed = Instant::now();
+
+        let mut metrics = Vec::new();
+        for (channel, counters) in &mut self.channels {
+            if counters.messages == 0 {
+                continue;
+            }
+            metrics.push(format!(
+                "twitch_archiver.{channel}.messages:{}|c",
+                counters.messages
+            ));
+            metrics.push(format!(
+                "twitch_archiver.{channel}.bytes:{}|c",
+                counters.bytes
+            ));
+            *counters = Counters::default();
+        }
+        if let Some(ping) = self.ping.take() {
+            metrics.push(format!("twitch_archiver.ping:{}|ms", ping.as_millis()));
+        }
+
+        let mut packet = String::new();
+        for metric in metrics {
+            if !packet.is_empty() && packet.len() + metric.len() + 1 > MAX_PACKET {
+                self.send(&packet);
+                packet.clear();
+            }
+            if !packet.is_empty() {
+                packet.push('\n');
+            }
+            packet.push_str(&metric);
+        }
+        if !packet.is_empty() {
+            self.send(&packet);
+        }
+    }
+
+    fn send(&self, packet: &str) {
+        // nobody listening is not worth stopping the archive for
+        if let Err(e) = self.socket.send(packet.as_bytes()) {
+            eprintln!("failed to send metrics to statsd: {e}");
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use crate::irc::Message;
+use anyhow::{bail, Context, Result};
+use chrono::SecondsFormat;
+use std::{
+    io::{self, Write},
+    net::{ToSocketAddrs, UdpSocket},
+};
+use tcp_stream::{TLSConfig, TcpStream};
+
+/// An RFC 5424 syslog client, the channel is sent as structured data
+pub enum Syslog {
+    Udp(UdpSocket),
+    /// TCP and TLS, both with octet-counting framing
+    Stream(TcpStream),
+}
+
+impl Syslog {
+    /// Connect to an address like udp://host:514, tcp://host:601 or
+    /// tls://host:6514
+    pub fn connect(url: &str) -> Result<Self> {
+        let (scheme, addr) = url
+            .split_once("://")
+            .context("syslog address should look like udp://host:port")?;
+        Ok(match scheme {
+            "udp" => {
+                let addr = addr
+                    .to_socket_addrs()?
+                    .next()
+                    .with_context(|| format!("{addr} did not resolve to anything"))?;
+                let local = if addr.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+                let socket = UdpSocket::bind(local)?;
+                socket.connect(addr)?;
+                Syslog::Udp(socket)
+            }
+            "tcp" => Syslog::Stream(TcpStream::connect(addr)?),
+            "tls" => {
+                let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+                let stream = TcpStream::connect(addr)?;
+                Syslog::Stream(stream.into_tls(host, TLSConfig::default())?)
+            }
+            _ => bail!("unknown syslog transport {scheme}, expected udp, tcp or tls"),
+        })
+    }
+
+    pub fn send(&mut self, msg: &Message, text: &[u8]) -> io::Result<()> {
+        let record = record(msg, text);
+        match self {
+            Syslog::Udp(socket) => socket.send(&record).map(drop),
+            Syslog::Stream(stream) => write_framed(stream, &record),
+        }
+    }
+}
+
+/// The RFC 5424 record of a message, without the framing
+fn record(msg: &Message, text: &[u8]) -> Vec<u8> {
+    let time = msg.sent_time().to_rfc3339_opts(SecondsFormat::Millis, true);
+
+    // <14> is user.info, and we don't bother with the hostname
+    let mut record = Vec::with_capacity(text.len() + 128);
+    write!(
+        record,
+        "<14>1 {time} - twitch-archiver {} {} ",
+        std::process::id(),
+        msg.command
+    )
+    .expect("writing to a vec");
+    // 32473 is the enterprise number reserved for examples
+    match msg.channel() {
+        Some(channel) => {
+            record.extend_from_slice(b"[twitch@32473 channel=\"");
+            // a PARAM-VALUE has to escape these three
+            for c in channel.bytes() {
+                if matches!(c, b'"' | b'\\' | b']') {
+                    record.push(b'\\');
+                }
+                record.push(c);
+            }
+            record.extend_from_slice(b"\"] ");
+        }
+        None => record.extend_from_slice(b"- "),
+    }
+    record.extend_from_slice(text);
+    record
+}
+
+/// Octet-counting framing (RFC 6587) for the stream transports
+fn write_framed(mut stream: impl Write, record: &[u8]) -> io::Result<()> {
+    write!(stream, "{} ", record.len())?;
+    stream.write_all(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framed(line: &str) -> String {
+        let msg = Message::parse(line);
+        let mut out = Vec::new();
+        write_framed(&mut out, &record(&msg, b"text")).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn octet_counting() {
+        let header = format!(
+            "<14>1 2024-01-31T12:00:00.000Z - twitch-archiver {} PRIVMSG ",
+            std::process::id()
+        );
+        let record = format!("{header}[twitch@32473 channel=\"forsen\"] text");
+        assert_eq!(
+            framed("@tmi-sent-ts=1706702400000 :a!a@a PRIVMSG #forsen :hi"),
+            format!("{} {record}", record.len())
+        );
+        let record = format!("{header}- text");
+        assert_eq!(
+            framed("@tmi-sent-ts=1706702400000 PRIVMSG"),
+            format!("{} {record}", record.len())
+        );
+    }
+
+    #[test]
+    fn escaped_channel() {
+        let out = framed(r#"@tmi-sent-ts=1706702400000 PRIVMSG #a"b\c]d :hi"#);
+        assert!(out.contains(r#"[twitch@32473 channel="a\"b\\c\]d"] text"#));
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{irc::Message, timezone::Timezone};
+use anyhow::{bail, Result};
+use file_rotate::{compression::Compression, suffix::AppendCount, ContentLimit, FileRotate};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// Where the messages without a channel go
+const NO_CHANNEL: &str = "_global";
+
+enum Part {
+    Text(String),
+    Channel,
+    ChannelId,
+    Command,
+    Date,
+    Year,
+    Month,
+}
+
+/// An output path like `logs/{channel}/{date}.log`, rendered for every
+/// message, with a rotated file for each of the rendered paths
+pub struct Templated {
+    parts: Vec<Part>,
+    timezone: Timezone,
+    rotation_limit: usize,
+    /// The open files by the path without its date parts, a file is
+    /// replaced once the date rolls over so that the old one gets closed
+    files: HashMap<String, (PathBuf, FileRotate<AppendCount>)>,
+    channel_ids: ChannelIds,
+}
+
+/// The room-id of every channel, learned from the ROOMSTATE sent on join or
+/// any message with the tag. It's kept across reconnects (and reopened
+/// outputs), the messages before the id is known go to the login path
+pub type ChannelIds = Rc<RefCell<HashMap<String, String>>>;
+
+/// Make a name that came from the wire safe to put into a path, so that
+/// `#../../x` can't write outside of the output directory
+pub fn path_safe(name: &str) -> Cow<'_, str> {
+    if !name.starts_with('.') && !name.contains(['/', '\\']) {
+        return Cow::Borrowed(name);
+    }
+    let mut safe = name.replace(['/', '\\'], "_");
+    if safe.starts_with('.') {
+        safe.replace_range(..1, "_");
+    }
+    Cow::Owned(safe)
+}
+
+pub fn rotating_file(path: impl AsRef<Path>, limit: usize) -> FileRotate<AppendCount> {
+    FileRotate::new(
+        path,
+        AppendCount::new(usize::MAX),
+        ContentLimit::BytesSurpassed(limit),
+        Compression::OnRotate(0),
+        None,
+    )
+}
+
+impl Templated {
+    /// Returns None if the path has no placeholders at all
+    pub fn parse(
+        path: &Path,
+        timezone: Timezone,
+        rotation_limit: usize,
+        channel_ids: ChannelIds,
+    ) -> Result<Option<Self>> {
+        let mut rest = &*path.to_string_lossy();
+        let mut parts = Vec::new();
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                bail!("unclosed {{ in the output path {}", path.display());
+            };
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_owned()));
+            }
+            parts.push(match &rest[start + 1..start + len] {
+                "channel" => Part::Channel,
+                "channel_id" => Part::ChannelId,
+                "command" => Part::Command,
+                "date" => Part::Date,
+                "year" => Part::Year,
+                "month" => Part::Month,
+                other => bail!(
+                    "unknown placeholder {{{other}}} in the output path, \
+                     expected {{channel}}, {{channel_id}}, {{command}}, {{date}}, {{year}} or {{month}}"
+                ),
+            });
+            rest = &rest[start + len + 1..];
+        }
+        if parts.is_empty() {
+            return Ok(None);
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_owned()));
+        }
+        Ok(Some(Self {
+            parts,
+            timezone,
+            rotation_limit,
+            files: HashMap::new(),
+            channel_ids,
+        }))
+    }
+
+    /// Returns the path and the key of its file, which is the path without
+    /// the date parts
+    fn render(&self, channel: &str, msg: &Message) -> (PathBuf, String) {
+        let time = msg.sent_time();
+        let mut path = String::new();
+        let mut key = String::new();
+        for part in &self.parts {
+            let text = match part {
+                Part::Text(text) => Cow::Borrowed(&**text),
+                Part::Channel => path_safe(channel),
+                // the login is better than nothing before the room-id is known
+                Part::ChannelId => {
+                    let ids = self.channel_ids.borrow();
+                    let id = ids.get(channel).map_or(channel, |id| id);
+                    Cow::Owned(path_safe(id).into_owned())
+                }
+                Part::Command => path_safe(msg.command),
+                Part::Date => {
+                    path.push_str(&self.timezone.format(time, "%Y-%m-%d"));
+                    continue;
+                }
+                Part::Year => {
+                    path.push_str(&self.timezone.format(time, "%Y"));
+                    continue;
+                }
+                Part::Month => {
+                    path.push_str(&self.timezone.format(time, "%m"));
+                    continue;
+                }
+            };
+            path.push_str(&text);
+            key.push_str(&text);
+        }
+        (path.into(), key)
+    }
+
+    /// The paths of the currently open files
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.files.values().map(|(path, _)| path)
+    }
+
+    pub fn write(&mut self, msg: &Message, line: &[u8]) -> io::Result<()> {
+        let channel = msg.channel().unwrap_or(NO_CHANNEL);
+        if let Some(id) = msg.tag("room-id") {
+            let mut ids = self.channel_ids.borrow_mut();
+            if ids.get(channel).map(String::as_str) != Some(&id.0) {
+                ids.insert(channel.to_owned(), id.0.to_string());
+            }
+        }
+        let (path, key) = self.render(channel, msg);
+        if !matches!(self.files.get(&key), Some((open, _)) if *open == path) {
+            // FileRotate creates the directory too, but panics if it fails
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let file = rotating_file(&path, self.rotation_limit);
+            self.files.insert(key.clone(), (path, file));
+        }
+        let (_, file) = self.files.get_mut(&key).expect("inserted above");
+        file.write_all(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsafe_names() {
+        assert_eq!(path_safe("forsen"), "forsen");
+        assert_eq!(path_safe(".."), "_.");
+        assert_eq!(path_safe("../../x"), "_._.._x");
+        assert_eq!(path_safe("a\\b"), "a_b");
+        assert_eq!(path_safe(".hidden"), "_hidden");
+    }
+}
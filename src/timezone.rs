@@ -0,0 +1,42 @@
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::str::FromStr;
+
+/// The timezone for the human-facing times, the system one by default
+#[derive(Clone, Copy)]
+pub enum Timezone {
+    Local,
+    Named(Tz),
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "local" {
+            return Ok(Timezone::Local);
+        }
+        s.parse()
+            .map(Timezone::Named)
+            .map_err(|_| format!("unknown timezone {s}, expected 'local' or an IANA name"))
+    }
+}
+
+impl Timezone {
+    pub fn format(self, time: DateTime<Utc>, fmt: &str) -> String {
+        match self {
+            Timezone::Local => time.with_timezone(&Local).format(fmt).to_string(),
+            Timezone::Named(tz) => time.with_timezone(&tz).format(fmt).to_string(),
+        }
+    }
+
+    /// The UTC time of a local time in this timezone, the earlier one if it
+    /// happened twice because of DST
+    pub fn to_utc(self, time: NaiveDateTime) -> Option<DateTime<Utc>> {
+        let time = match self {
+            Timezone::Local => Local.from_local_datetime(&time).earliest()?.naive_utc(),
+            Timezone::Named(tz) => tz.from_local_datetime(&time).earliest()?.naive_utc(),
+        };
+        Some(time.and_utc())
+    }
+}